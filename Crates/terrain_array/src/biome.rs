@@ -0,0 +1,79 @@
+use ndarray::Array2;
+// Not `Perlin`: noise exports two structs with that name, which is ambiguous
+use noise::{NoiseFn, Seedable, SuperSimplex};
+
+/// Ground type of a cell, affecting how fast blight spreads and how hard it is to clean.
+///
+/// Stored in the biome layer as its splat value, so it can be uploaded to the shader as-is.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Biome {
+    LushGrass,
+    DryMud,
+    Rock,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 3] = [Biome::LushGrass, Biome::DryMud, Biome::Rock];
+
+    /// Maps a grayscale value (e.g. from a biome image) to the closest biome.
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            0..=84 => Biome::LushGrass,
+            85..=169 => Biome::DryMud,
+            _ => Biome::Rock,
+        }
+    }
+
+    /// The value stored in the biome layer.
+    pub fn splat_value(self) -> u8 {
+        match self {
+            Biome::LushGrass => 0,
+            Biome::DryMud => 128,
+            Biome::Rock => 255,
+        }
+    }
+
+    /// Index into [`Biome::ALL`].
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Number of dilation steps blight takes on this ground, relative to the base speed.
+    pub fn spread_multiplier(self) -> f32 {
+        match self {
+            Biome::LushGrass => 1.0,
+            Biome::DryMud => 1.5, // Dry ground is where blight feels at home
+            Biome::Rock => 0.5,
+        }
+    }
+
+    /// Fraction of blight that survives a cleaning pass, between 0 (easy) and 1 (impossible).
+    pub fn clean_difficulty(self) -> f32 {
+        match self {
+            Biome::LushGrass => 0.0,
+            Biome::DryMud => 0.3,
+            Biome::Rock => 0.6,
+        }
+    }
+}
+
+/// Procedurally generates a biome layer of the given size from simplex noise.
+pub fn generate_biomes(width: usize, height: usize, seed: u32) -> Array2<u8> {
+    let noise = SuperSimplex::new().set_seed(seed);
+
+    Array2::from_shape_fn((width, height), |(i, j)| {
+        let value = noise.get([
+            i as f64 / height as f64 * 6.0,
+            j as f64 / width as f64 * 6.0,
+        ]);
+
+        let biome = if value < -0.2 {
+            Biome::DryMud
+        } else if value > 0.35 {
+            Biome::Rock
+        } else {
+            Biome::LushGrass
+        };
+        biome.splat_value()
+    })
+}
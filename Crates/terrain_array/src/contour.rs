@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use ndarray::Array2;
+
+/// Polyline along which a layer crosses a threshold, in grid coordinates (`[row, column]`).
+#[derive(PartialEq, Debug, Clone)]
+pub struct Contour {
+    pub points: Vec<[f32; 2]>,
+    /// Closed contours connect their last point back to the first one.
+    /// Open ones end at the border of the grid.
+    pub closed: bool,
+}
+
+impl Contour {
+    pub fn length(&self) -> f32 {
+        let closing = if self.closed {
+            self.points.last().zip(self.points.first())
+        } else {
+            None
+        };
+
+        self.points
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .chain(closing.map(|(a, b)| (*a, *b)))
+            .map(|(a, b)| ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt())
+            .sum()
+    }
+}
+
+/// Total length of all contours, e.g. the blight frontier.
+pub fn total_length(contours: &[Contour]) -> f32 {
+    contours.iter().map(Contour::length).sum()
+}
+
+/// Identifies the edge between two horizontally or vertically adjacent grid points.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+enum Edge {
+    /// Between `[i, j]` and `[i, j + 1]`
+    Horizontal(usize, usize),
+    /// Between `[i, j]` and `[i + 1, j]`
+    Vertical(usize, usize),
+}
+
+/// Marching squares: returns the lines separating values `> threshold` from the others.
+pub fn extract_contours(data: &Array2<u8>, threshold: u8) -> Vec<Contour> {
+    let (rows, cols) = data.dim();
+    let inside = |i: usize, j: usize| data[[i, j]] > threshold;
+
+    // Point where the threshold is crossed along an edge, linearly interpolated
+    let crossing = |edge: Edge| -> [f32; 2] {
+        let ((i0, j0), (i1, j1)) = match edge {
+            Edge::Horizontal(i, j) => ((i, j), (i, j + 1)),
+            Edge::Vertical(i, j) => ((i, j), (i + 1, j)),
+        };
+        let a = data[[i0, j0]] as f32;
+        let b = data[[i1, j1]] as f32;
+
+        let t = (threshold as f32 + 0.5 - a) / (b - a);
+        [
+            i0 as f32 + t * (i1 - i0) as f32,
+            j0 as f32 + t * (j1 - j0) as f32,
+        ]
+    };
+
+    let mut segments = Vec::new();
+    for i in 0..rows.saturating_sub(1) {
+        for j in 0..cols.saturating_sub(1) {
+            let case = (inside(i, j) as u8) << 3
+                | (inside(i, j + 1) as u8) << 2
+                | (inside(i + 1, j + 1) as u8) << 1
+                | (inside(i + 1, j) as u8);
+
+            let top = Edge::Horizontal(i, j);
+            let bottom = Edge::Horizontal(i + 1, j);
+            let left = Edge::Vertical(i, j);
+            let right = Edge::Vertical(i, j + 1);
+
+            match case {
+                0 | 15 => {}
+                1 | 14 => segments.push((left, bottom)),
+                2 | 13 => segments.push((bottom, right)),
+                3 | 12 => segments.push((left, right)),
+                4 | 11 => segments.push((top, right)),
+                6 | 9 => segments.push((top, bottom)),
+                7 | 8 => segments.push((left, top)),
+                5 | 10 => {
+                    // Saddle: use the cell's average to decide whether the diagonals connect
+                    let sum = data[[i, j]] as u32
+                        + data[[i, j + 1]] as u32
+                        + data[[i + 1, j + 1]] as u32
+                        + data[[i + 1, j]] as u32;
+                    let center_inside = sum > threshold as u32 * 4;
+
+                    if (case == 10) == center_inside {
+                        segments.push((left, bottom));
+                        segments.push((top, right));
+                    } else {
+                        segments.push((left, top));
+                        segments.push((bottom, right));
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    join_segments(&segments)
+        .into_iter()
+        .map(|(edges, closed)| Contour {
+            points: edges.into_iter().map(crossing).collect(),
+            closed,
+        })
+        .collect()
+}
+
+/// Chains segments sharing an edge into polylines. Returns the edges of each line, and whether it's closed.
+fn join_segments(segments: &[(Edge, Edge)]) -> Vec<(Vec<Edge>, bool)> {
+    let mut segments_by_edge: HashMap<Edge, Vec<usize>> = HashMap::new();
+    for (index, (a, b)) in segments.iter().enumerate() {
+        segments_by_edge.entry(*a).or_default().push(index);
+        segments_by_edge.entry(*b).or_default().push(index);
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut lines = Vec::new();
+
+    // Open lines first (they start at an edge with a single segment), so they are not split by the
+    // closed-line pass below.
+    let mut open_starts: Vec<Edge> = segments_by_edge
+        .iter()
+        .filter(|(_, indices)| indices.len() == 1)
+        .map(|(edge, _)| *edge)
+        .collect();
+    open_starts.sort_by_key(|edge| match edge {
+        Edge::Horizontal(i, j) => (*i, *j, 0),
+        Edge::Vertical(i, j) => (*i, *j, 1),
+    });
+
+    for start in open_starts {
+        let index = segments_by_edge[&start][0];
+        if !used[index] {
+            lines.push((
+                trace(start, index, segments, &segments_by_edge, &mut used),
+                false,
+            ));
+        }
+    }
+
+    for index in 0..segments.len() {
+        if !used[index] {
+            let start = segments[index].0;
+            let mut edges = trace(start, index, segments, &segments_by_edge, &mut used);
+            edges.pop(); // Last edge is the start again
+            lines.push((edges, true));
+        }
+    }
+
+    lines
+}
+
+fn trace(
+    start: Edge,
+    first_segment: usize,
+    segments: &[(Edge, Edge)],
+    segments_by_edge: &HashMap<Edge, Vec<usize>>,
+    used: &mut [bool],
+) -> Vec<Edge> {
+    let mut edges = vec![start];
+    let mut current = start;
+    let mut segment = Some(first_segment);
+
+    while let Some(index) = segment {
+        used[index] = true;
+        let (a, b) = segments[index];
+        current = if a == current { b } else { a };
+        edges.push(current);
+
+        segment = segments_by_edge[&current]
+            .iter()
+            .copied()
+            .find(|next| !used[*next]);
+    }
+
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr2, s};
+
+    use super::*;
+
+    /// Corners cut off by each contour of a single 2x2 cell, sorted.
+    fn cut_corners(contours: &[Contour]) -> Vec<[usize; 2]> {
+        let mut corners: Vec<[usize; 2]> = contours
+            .iter()
+            .map(|contour| {
+                assert_eq!(contour.points.len(), 2);
+                let [a, b] = [contour.points[0], contour.points[1]];
+                [
+                    ((a[0] + b[0]) / 2.0).round() as usize,
+                    ((a[1] + b[1]) / 2.0).round() as usize,
+                ]
+            })
+            .collect();
+        corners.sort_unstable();
+        corners
+    }
+
+    #[test]
+    fn blob_gives_closed_loop() {
+        let mut data = Array2::zeros((5, 5));
+        data[[2, 2]] = 255;
+
+        let contours = extract_contours(&data, 127);
+
+        assert_eq!(contours.len(), 1);
+        assert!(contours[0].closed);
+        assert_eq!(contours[0].points.len(), 4);
+        for point in contours[0].points.iter() {
+            let distance = (point[0] - 2.0).abs() + (point[1] - 2.0).abs();
+            assert!((distance - 0.5).abs() < 1e-6, "{point:?}");
+        }
+        assert!((total_length(&contours) - 2.0 * 2f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn blob_at_border_gives_open_line() {
+        let mut data = Array2::zeros((4, 4));
+        data.slice_mut(s![..2, ..2]).fill(255);
+
+        let contours = extract_contours(&data, 127);
+
+        assert_eq!(contours.len(), 1);
+        assert!(!contours[0].closed);
+        let mut ends = [contours[0].points[0], *contours[0].points.last().unwrap()];
+        ends.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(ends, [[0.0, 1.5], [1.5, 0.0]]);
+        // Straight along both sides, cutting the corner diagonally
+        assert!((contours[0].length() - (2.0 + 0.5f32.sqrt())).abs() < 1e-5);
+    }
+
+    #[test]
+    fn saddle_with_inside_center_connects_diagonal() {
+        // Inside corners on the main diagonal, their average above the threshold
+        let contours = extract_contours(&arr2(&[[255, 0], [0, 255]]), 127);
+        assert_eq!(cut_corners(&contours), [[0, 1], [1, 0]]);
+
+        // Inside corners on the other diagonal
+        let contours = extract_contours(&arr2(&[[0, 255], [255, 0]]), 127);
+        assert_eq!(cut_corners(&contours), [[0, 0], [1, 1]]);
+    }
+
+    #[test]
+    fn saddle_with_outside_center_separates_corners() {
+        let contours = extract_contours(&arr2(&[[200, 0], [0, 200]]), 127);
+        assert_eq!(cut_corners(&contours), [[0, 0], [1, 1]]);
+
+        let contours = extract_contours(&arr2(&[[0, 200], [200, 0]]), 127);
+        assert_eq!(cut_corners(&contours), [[0, 1], [1, 0]]);
+    }
+}
@@ -0,0 +1,192 @@
+/// Point of an [`EscalationSchedule`]. Between keyframes, values are linearly interpolated.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct EscalationKeyframe {
+    /// Elapsed simulation time in seconds at which the keyframe is reached
+    pub time: f32,
+    /// Seconds between two simulation steps
+    pub step_interval: f32,
+    /// Multiplies the number of dilation steps every strain takes per simulation step
+    pub aggressiveness: f32,
+    /// Expected number of new outbreaks per minute
+    pub outbreak_rate: f32,
+}
+
+/// State of the escalation at some point in time.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Escalation {
+    /// Number of keyframes reached so far (minus the first one), for display purposes
+    pub level: usize,
+    pub step_interval: f32,
+    pub aggressiveness: f32,
+    pub outbreak_rate: f32,
+}
+
+/// How the blight gets more dangerous over elapsed simulation time.
+#[derive(PartialEq, Debug, Clone)]
+pub struct EscalationSchedule {
+    keyframes: Vec<EscalationKeyframe>,
+}
+
+impl EscalationSchedule {
+    /// Creates a schedule from keyframes sorted by time. After the last keyframe, its values are
+    /// kept.
+    ///
+    /// Fails if there are no keyframes, if their times don't increase, or if one of them has a
+    /// value that isn't finite or a step interval that isn't positive, since the simulation thread
+    /// sleeps for that long.
+    pub fn new(keyframes: Vec<EscalationKeyframe>) -> Result<Self, String> {
+        if keyframes.is_empty() {
+            return Err("Escalation needs at least one keyframe".to_string());
+        }
+        for keyframe in keyframes.iter() {
+            let values = [
+                keyframe.time,
+                keyframe.step_interval,
+                keyframe.aggressiveness,
+                keyframe.outbreak_rate,
+            ];
+            if !values.iter().all(|value| value.is_finite()) {
+                return Err(format!(
+                    "Keyframe {:?} has a value that isn't finite",
+                    keyframe
+                ));
+            }
+            if keyframe.step_interval <= 0.0 {
+                return Err(format!(
+                    "Keyframe {:?} needs a positive step interval",
+                    keyframe
+                ));
+            }
+        }
+        if let Some(pair) = keyframes
+            .windows(2)
+            .find(|pair| pair[0].time >= pair[1].time)
+        {
+            return Err(format!(
+                "Keyframe at {} comes after the one at {}",
+                pair[1].time, pair[0].time
+            ));
+        }
+
+        Ok(Self { keyframes })
+    }
+
+    pub fn at(&self, time: f32) -> Escalation {
+        let level = self
+            .keyframes
+            .iter()
+            .rposition(|keyframe| keyframe.time <= time)
+            .unwrap_or(0);
+
+        let from = self.keyframes[level];
+        let to = self.keyframes.get(level + 1).copied().unwrap_or(from);
+        let t = if to.time > from.time {
+            ((time - from.time) / (to.time - from.time)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+
+        Escalation {
+            level,
+            step_interval: lerp(from.step_interval, to.step_interval),
+            aggressiveness: lerp(from.aggressiveness, to.aggressiveness),
+            outbreak_rate: lerp(from.outbreak_rate, to.outbreak_rate),
+        }
+    }
+}
+
+impl Default for EscalationSchedule {
+    fn default() -> Self {
+        let keyframe = |time, step_interval, aggressiveness, outbreak_rate| EscalationKeyframe {
+            time,
+            step_interval,
+            aggressiveness,
+            outbreak_rate,
+        };
+
+        Self::new(vec![
+            keyframe(0.0, 0.5, 1.0, 0.0),
+            keyframe(120.0, 0.45, 1.2, 0.5),
+            keyframe(300.0, 0.4, 1.5, 1.0),
+            keyframe(600.0, 0.3, 2.0, 2.0),
+            keyframe(900.0, 0.25, 2.5, 3.0),
+        ])
+        .expect("Default escalation schedule is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, step_interval: f32) -> EscalationKeyframe {
+        EscalationKeyframe {
+            time,
+            step_interval,
+            aggressiveness: 1.0 + time / 10.0,
+            outbreak_rate: time,
+        }
+    }
+
+    fn schedule() -> EscalationSchedule {
+        EscalationSchedule::new(vec![keyframe(10.0, 0.5), keyframe(20.0, 0.3)]).unwrap()
+    }
+
+    #[test]
+    fn keeps_first_keyframe_before_it() {
+        let escalation = schedule().at(0.0);
+
+        assert_eq!(escalation.level, 0);
+        assert_eq!(escalation.step_interval, 0.5);
+        assert_eq!(escalation.outbreak_rate, 10.0);
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let escalation = schedule().at(15.0);
+
+        assert_eq!(escalation.level, 0);
+        assert!((escalation.step_interval - 0.4).abs() < 1e-6);
+        assert!((escalation.aggressiveness - 2.5).abs() < 1e-6);
+        assert!((escalation.outbreak_rate - 15.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn keeps_last_keyframe_after_it() {
+        let schedule = schedule();
+
+        assert_eq!(schedule.at(20.0).level, 1);
+        let escalation = schedule.at(100.0);
+        assert_eq!(escalation.level, 1);
+        assert_eq!(escalation.step_interval, 0.3);
+        assert_eq!(escalation.aggressiveness, 3.0);
+        assert_eq!(escalation.outbreak_rate, 20.0);
+    }
+
+    #[test]
+    fn rejects_unsorted_keyframes() {
+        let unsorted = vec![keyframe(20.0, 0.3), keyframe(10.0, 0.5)];
+        assert!(EscalationSchedule::new(unsorted).is_err());
+
+        let same_time = vec![keyframe(10.0, 0.3), keyframe(10.0, 0.5)];
+        assert!(EscalationSchedule::new(same_time).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_keyframes() {
+        assert!(EscalationSchedule::new(vec![]).is_err());
+        assert!(EscalationSchedule::new(vec![keyframe(0.0, 0.0)]).is_err());
+        assert!(EscalationSchedule::new(vec![keyframe(0.0, -1.0)]).is_err());
+        assert!(EscalationSchedule::new(vec![keyframe(0.0, f32::NAN)]).is_err());
+        assert!(EscalationSchedule::new(vec![keyframe(f32::INFINITY, 0.5)]).is_err());
+    }
+
+    #[test]
+    fn default_schedule_is_valid() {
+        let schedule = EscalationSchedule::default();
+
+        assert_eq!(schedule.at(0.0).level, 0);
+        assert!(schedule.at(10_000.0).level > 0);
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    ops::Range,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc::{Receiver, Sender},
@@ -11,13 +12,29 @@ use std::{
 
 use ndarray::{s, Array2};
 use noise::{NoiseFn, Perlin};
+use rand::Rng;
+
+mod biome;
+mod contour;
+mod escalation;
+mod strain;
+
+pub use biome::*;
+pub use contour::*;
+pub use escalation::*;
+pub use strain::*;
 
 #[derive(Debug)]
 pub struct TerrainArray {
-    data_read: Array2<u8>,
-    shapes: HashMap<Shape, u8>,
-    shapes_sender: Sender<HashMap<Shape, u8>>,
-    outputs_receiver: Receiver<Array2<u8>>,
+    data_read: Layers,
+    commands: Commands,
+    commands_sender: Sender<Commands>,
+    outputs_receiver: Receiver<(Layers, f32)>,
+    /// Simulation time in seconds of `data_read`
+    elapsed: f32,
+    schedule: EscalationSchedule,
+    /// Every barrier by id; cells covered by several take the strongest
+    barriers: HashMap<i64, (Shape, u8)>,
     thread: Option<JoinHandle<()>>,
     shutdown: Arc<AtomicBool>,
 }
@@ -25,51 +42,258 @@ pub struct TerrainArray {
 pub const BLIGHT: u8 = u8::MAX;
 pub const CLEAN: u8 = 0u8;
 
+/// How much moisture evaporates per simulation step
+const MOISTURE_DECAY: u8 = 2;
+/// How fast the "recently cleaned" flag fades out per simulation step
+const CLEANED_DECAY: u8 = 32;
+
+/// One of the per-cell values computed by the simulation thread.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Layer {
+    Blight,
+    Age,
+    Moisture,
+    Cleaned,
+    Biome,
+    Barrier,
+}
+
+impl Layer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Blight" => Some(Layer::Blight),
+            "Age" => Some(Layer::Age),
+            "Moisture" => Some(Layer::Moisture),
+            "Cleaned" => Some(Layer::Cleaned),
+            "Biome" => Some(Layer::Biome),
+            "Barrier" => Some(Layer::Barrier),
+            _ => None,
+        }
+    }
+}
+
+/// Snapshot of all simulation layers, each with dimensions `WIDTH x HEIGHT`.
+#[derive(Debug, Clone)]
+pub struct Layers {
+    pub blight: Array2<u8>,
+    /// Number of steps a cell has been blighted, saturating at 255
+    pub age: Array2<u8>,
+    /// Water left behind by cleaning, which slowly dries out
+    pub moisture: Array2<u8>,
+    /// 255 right after a cell has been cleaned, then fading out
+    pub cleaned: Array2<u8>,
+    /// Ground type, see [`Biome::splat_value`]
+    pub biome: Array2<u8>,
+    /// Which strain the blight in a cell belongs to, see [`StrainId`]
+    pub strain: Array2<StrainId>,
+    /// How likely blight spreading into a cell is held back at each step; 255 blocks it entirely
+    pub barrier: Array2<u8>,
+}
+
+impl Layers {
+    fn new(biome: Array2<u8>) -> Self {
+        let dim = biome.raw_dim();
+        Self {
+            blight: Array2::from_elem(dim, CLEAN),
+            age: Array2::zeros(dim),
+            moisture: Array2::zeros(dim),
+            cleaned: Array2::zeros(dim),
+            biome,
+            strain: Array2::from_elem(dim, ORIGINAL_STRAIN),
+            barrier: Array2::zeros(dim),
+        }
+    }
+
+    pub fn get(&self, layer: Layer) -> &Array2<u8> {
+        match layer {
+            Layer::Blight => &self.blight,
+            Layer::Age => &self.age,
+            Layer::Moisture => &self.moisture,
+            Layer::Cleaned => &self.cleaned,
+            Layer::Biome => &self.biome,
+            Layer::Barrier => &self.barrier,
+        }
+    }
+}
+
+/// Changes collected on the main thread, applied by the simulation thread in its next step.
+#[derive(Debug, Default)]
+struct Commands {
+    shapes: HashMap<Shape, (u8, StrainId)>,
+    /// All barriers, if they changed
+    barriers: Option<Vec<(Shape, u8)>>,
+    biomes: Option<Array2<u8>>,
+    schedule: Option<EscalationSchedule>,
+}
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Shape {
-    Circle { center: [usize; 2], radius: usize },
+    Circle {
+        center: [usize; 2],
+        radius: usize,
+    },
+    /// All cells within `radius` of the segment between `from` and `to`
+    Capsule {
+        from: [usize; 2],
+        to: [usize; 2],
+        radius: usize,
+    },
+}
+
+impl Shape {
+    /// Rows and columns of the cells the shape may cover, clamped to a grid of size `dim`.
+    fn bounds(&self, dim: (usize, usize)) -> (Range<usize>, Range<usize>) {
+        let (min, max, radius) = match *self {
+            Shape::Circle { center, radius } => (center, center, radius),
+            Shape::Capsule { from, to, radius } => (
+                [from[0].min(to[0]), from[1].min(to[1])],
+                [from[0].max(to[0]), from[1].max(to[1])],
+                radius,
+            ),
+        };
+
+        let rows = min[0].saturating_sub(radius)..(max[0] + radius + 1).min(dim.0);
+        let cols = min[1].saturating_sub(radius)..(max[1] + radius + 1).min(dim.1);
+        (
+            rows.start.min(rows.end)..rows.end,
+            cols.start.min(cols.end)..cols.end,
+        )
+    }
+
+    /// Distance of a cell to the shape's core relative to the radius: between 0 and 1 inside
+    /// the shape, `None` outside.
+    fn falloff(&self, pos: [usize; 2]) -> Option<f32> {
+        let pos = [pos[0] as f32, pos[1] as f32];
+        let (closest, radius) = match *self {
+            Shape::Circle { center, radius } => ([center[0] as f32, center[1] as f32], radius),
+            Shape::Capsule { from, to, radius } => {
+                let from = [from[0] as f32, from[1] as f32];
+                let to = [to[0] as f32, to[1] as f32];
+                let dir = [to[0] - from[0], to[1] - from[1]];
+                let len_sq = dir[0] * dir[0] + dir[1] * dir[1];
+                let t = if len_sq > 0.0 {
+                    (((pos[0] - from[0]) * dir[0] + (pos[1] - from[1]) * dir[1]) / len_sq)
+                        .clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                ([from[0] + t * dir[0], from[1] + t * dir[1]], radius)
+            }
+        };
+
+        let dist_sq = (pos[0] - closest[0]).powi(2) + (pos[1] - closest[1]).powi(2);
+        if dist_sq > (radius * radius) as f32 {
+            None
+        } else if radius == 0 {
+            Some(0.0)
+        } else {
+            Some(dist_sq.sqrt() / radius as f32)
+        }
+    }
+}
+
+/// Blight statistics over the cells of a shape.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct ShapeStats {
+    pub average: u8,
+    pub max: u8,
+    /// Fraction of cells with any blight
+    pub blighted_fraction: f32,
+    /// Blight-weighted average of the strains' damage multipliers, 1 if there is no blight
+    pub damage_multiplier: f32,
 }
 
 impl TerrainArray {
     pub const WIDTH: usize = 512;
     pub const HEIGHT: usize = 512;
 
+    /// Creates a terrain with procedurally generated biomes.
     pub fn new() -> Self {
-        let (shapes_sender, shapes_receiver): (_, Receiver<HashMap<Shape, u8>>) =
+        Self::with_biomes(generate_biomes(Self::WIDTH, Self::HEIGHT, rand::random()))
+    }
+
+    /// Creates a terrain with the given biome layer, see [`Biome::splat_value`].
+    pub fn with_biomes(biomes: Array2<u8>) -> Self {
+        assert_eq!(biomes.dim(), (Self::WIDTH, Self::HEIGHT));
+
+        let (commands_sender, commands_receiver): (_, Receiver<Commands>) =
             std::sync::mpsc::channel();
         let (outputs_sender, outputs_receiver) = std::sync::mpsc::channel();
 
         let shutdown = Arc::new(AtomicBool::new(false));
         let shutdown_inner = shutdown.clone();
 
+        let data_read = Layers::new(biomes);
+        let mut layers = data_read.clone();
+        let schedule = EscalationSchedule::default();
+        let mut schedule_inner = schedule.clone();
+
         let thread = std::thread::spawn(move || {
-            let mut array = Array2::from_elem((Self::WIDTH, Self::HEIGHT), CLEAN);
-            let ijs =
-                Array2::from_shape_fn((Self::WIDTH, Self::HEIGHT), |(i, j)| (i as f64, j as f64));
+            let kernel_noise = Self::kernel_noise();
+            let mut rng = rand::thread_rng();
+            let mut spread_progress = [[0.0; Biome::ALL.len()]; STRAINS.len()];
+            let mut elapsed = 0.0;
 
-            outputs_sender.send(array.clone()).unwrap();
+            outputs_sender.send((layers.clone(), elapsed)).unwrap();
 
             while !shutdown_inner.load(Ordering::Relaxed) {
-                if let Ok(input) = shapes_receiver.recv() {
+                if let Ok(input) = commands_receiver.recv() {
                     let start_time = Instant::now();
-                    for (shape, fill) in input.into_iter() {
-                        Self::do_fill_shape(&mut array, shape, fill);
+                    if let Some(biomes) = input.biomes {
+                        layers.biome = biomes;
+                    }
+                    if let Some(schedule) = input.schedule {
+                        schedule_inner = schedule;
+                    }
+                    let escalation = schedule_inner.at(elapsed);
+
+                    Self::do_decay(&mut layers);
+                    if let Some(barriers) = input.barriers {
+                        Self::do_set_barriers(&mut layers, barriers);
+                    }
+                    for (shape, (fill, strain)) in input.shapes.into_iter() {
+                        Self::do_fill_shape(&mut layers, shape, fill, strain);
+                    }
+
+                    // Slow strains and biomes skip steps, fast ones take several
+                    for (strain_id, strain) in STRAINS.iter().enumerate() {
+                        let steps_by_biome = Biome::ALL.map(|biome| {
+                            let progress = &mut spread_progress[strain_id][biome.index()];
+                            *progress += strain.speed
+                                * biome.spread_multiplier()
+                                * escalation.aggressiveness;
+                            let steps = progress.floor();
+                            *progress -= steps;
+                            steps as usize
+                        });
+                        Self::do_dilate(
+                            &kernel_noise,
+                            &mut layers,
+                            strain_id as StrainId,
+                            steps_by_biome,
+                            &mut rng,
+                        );
                     }
-                    Self::do_dilate(&ijs, &mut array);
-                    outputs_sender.send(array.clone()).unwrap();
+                    Self::do_age(&mut layers);
+                    elapsed += escalation.step_interval;
+                    outputs_sender.send((layers.clone(), elapsed)).unwrap();
 
-                    let elapsed = Instant::now().duration_since(start_time);
-                    let sleep = Duration::from_millis(500).saturating_sub(elapsed);
+                    let step_time = Instant::now().duration_since(start_time);
+                    let sleep =
+                        Duration::from_secs_f32(escalation.step_interval).saturating_sub(step_time);
                     std::thread::sleep(sleep);
                 }
             }
         });
 
         Self {
-            data_read: Array2::from_elem((Self::WIDTH, Self::HEIGHT), CLEAN),
-            shapes: HashMap::new(),
-            shapes_sender,
+            data_read,
+            commands: Commands::default(),
+            commands_sender,
             outputs_receiver,
+            elapsed: 0.0,
+            schedule,
+            barriers: HashMap::new(),
             thread: Some(thread),
             shutdown,
         }
@@ -80,131 +304,257 @@ impl TerrainArray {
         self.thread.take().unwrap().join().unwrap();
     }
 
-    fn do_fill_shape(data_write: &mut Array2<u8>, shape: Shape, fill: u8) {
-        match shape {
-            Shape::Circle { center, radius } => {
-                let window_size = radius * 2 + 1;
-                let wi = center[0].saturating_sub(radius);
-                let wj = center[1].saturating_sub(radius);
-                data_write
-                    .slice_mut(s![wi..wi + window_size, wj..wj + window_size])
-                    .indexed_iter_mut()
-                    .for_each(|((i, j), value)| {
-                        let di = radius as isize - i as isize;
-                        let dj = radius as isize - j as isize;
-                        let dist_sq = (di * di + dj * dj) as usize;
-                        if dist_sq <= radius * radius {
-                            if fill == CLEAN {
-                                *value = fill
-                                    + ((*value as f32) * ((dist_sq as f32).sqrt() / radius as f32))
-                                        as u8
-                            } else {
-                                *value = fill
-                                    - ((fill as f32) * ((dist_sq as f32).sqrt() / radius as f32))
-                                        as u8
-                            }
+    fn do_fill_shape(layers: &mut Layers, shape: Shape, fill: u8, strain: StrainId) {
+        let (rows, cols) = shape.bounds(layers.blight.dim());
+        let offset = [rows.start, cols.start];
+        let window = s![rows, cols];
+        ndarray::Zip::indexed(layers.blight.slice_mut(window))
+            .and(layers.moisture.slice_mut(window))
+            .and(layers.cleaned.slice_mut(window))
+            .and(layers.biome.slice(window))
+            .and(layers.strain.slice_mut(window))
+            .for_each(|(i, j), value, moisture, cleaned, biome, owner| {
+                if let Some(falloff) = shape.falloff([offset[0] + i, offset[1] + j]) {
+                    if fill == CLEAN {
+                        let old_value = *value;
+                        let biome_difficulty = Biome::from_value(*biome).clean_difficulty();
+                        let strain_resistance = Strain::get(*owner).clean_resistance;
+                        let difficulty = 1.0 - (1.0 - biome_difficulty) * (1.0 - strain_resistance);
+                        let remaining = falloff + (1.0 - falloff) * difficulty;
+                        *value = fill + ((*value as f32) * remaining) as u8;
+
+                        *moisture = (*moisture).max(((1.0 - falloff) * 255.0) as u8);
+                        if *value < old_value {
+                            *cleaned = u8::MAX;
                         }
-                    });
-            }
+                    } else {
+                        *value = fill - ((fill as f32) * falloff) as u8;
+                        *owner = strain;
+                    }
+                }
+            });
+    }
+
+    /// Rebuilds the barrier layer from scratch, so that removed barriers don't leave holes in
+    /// overlapping ones.
+    fn do_set_barriers(layers: &mut Layers, barriers: Vec<(Shape, u8)>) {
+        layers.barrier.fill(0);
+        for (shape, strength) in barriers {
+            let (rows, cols) = shape.bounds(layers.barrier.dim());
+            let offset = [rows.start, cols.start];
+            ndarray::Zip::indexed(layers.barrier.slice_mut(s![rows, cols])).for_each(
+                |(i, j), barrier| {
+                    if shape.falloff([offset[0] + i, offset[1] + j]).is_some() {
+                        *barrier = (*barrier).max(strength);
+                    }
+                },
+            );
         }
     }
 
+    /// Lets moisture evaporate and the "recently cleaned" flag fade out.
+    fn do_decay(layers: &mut Layers) {
+        layers
+            .moisture
+            .mapv_inplace(|m| m.saturating_sub(MOISTURE_DECAY));
+        layers
+            .cleaned
+            .mapv_inplace(|c| c.saturating_sub(CLEANED_DECAY));
+    }
+
+    /// Advances the age of blighted cells; clean cells are reset.
+    fn do_age(layers: &mut Layers) {
+        ndarray::Zip::from(&mut layers.age)
+            .and(&layers.blight)
+            .for_each(|age, blight| {
+                *age = if *blight == CLEAN {
+                    0
+                } else {
+                    age.saturating_add(1)
+                };
+            });
+    }
+
     pub fn fill_shape(&mut self, shape: Shape, fill: u8) {
-        self.shapes.insert(shape, fill);
+        self.commands.shapes.insert(shape, (fill, ORIGINAL_STRAIN));
     }
 
-    pub fn query_shape_avg(&self, shape: Shape) -> u8 {
-        match shape {
-            Shape::Circle { center, radius } => {
-                let mut sum: usize = 0;
-                let mut count: usize = 0;
-                let window_size = radius * 2 + 1;
-                let wi = center[0].saturating_sub(radius);
-                let wj = center[1].saturating_sub(radius);
-                self.data_read
-                    .slice(s![wi..wi + window_size, wj..wj + window_size])
-                    .indexed_iter()
-                    .for_each(|((i, j), value)| {
-                        let di = radius as isize - i as isize;
-                        let dj = radius as isize - j as isize;
-                        let dist_sq = (di * di + dj * dj) as usize;
-                        if dist_sq <= radius * radius {
-                            sum += *value as usize;
-                            count += 1;
-                        }
-                    });
-                (sum / count) as u8
-            }
+    /// Fills a shape with blight of the given strain.
+    pub fn spawn_strain(&mut self, shape: Shape, strain: StrainId) {
+        self.commands.shapes.insert(shape, (BLIGHT, strain));
+    }
+
+    /// Sets how likely barrier `id` holds back blight spreading into the cells of a shape, 0 to
+    /// remove it. Where barriers overlap, the strongest one counts.
+    pub fn set_barrier(&mut self, id: i64, shape: Shape, strength: u8) {
+        if strength == 0 {
+            self.barriers.remove(&id);
+        } else {
+            self.barriers.insert(id, (shape, strength));
         }
+        self.commands.barriers = Some(self.barriers.values().cloned().collect());
+    }
+
+    /// Replaces the biome layer, see [`Biome::splat_value`].
+    pub fn set_biomes(&mut self, biomes: Array2<u8>) {
+        assert_eq!(biomes.dim(), (Self::WIDTH, Self::HEIGHT));
+        self.commands.biomes = Some(biomes);
+    }
+
+    /// Replaces the escalation schedule; elapsed time is kept.
+    pub fn set_escalation_schedule(&mut self, schedule: EscalationSchedule) {
+        self.schedule = schedule.clone();
+        self.commands.schedule = Some(schedule);
     }
 
-    pub fn do_dilate(ijs: &Array2<(f64, f64)>, data_write: &mut Array2<u8>) {
-        let mut new_data = Array2::zeros(data_write.raw_dim());
-
-        let kernels = [
-            ndarray::array![
-                [0, 0, 1, 0, 0],
-                [0, 0, 1, 0, 0],
-                [0, 1, 1, 1, 0],
-                [0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0],
-            ],
-            ndarray::array![
-                [0, 0, 0, 0, 0],
-                [0, 0, 1, 0, 0],
-                [0, 0, 1, 1, 1],
-                [0, 0, 1, 0, 0],
-                [0, 0, 0, 0, 0],
-            ],
-            ndarray::array![
-                [0, 0, 0, 0, 0],
-                [0, 0, 1, 0, 0],
-                [1, 1, 1, 0, 0],
-                [0, 0, 1, 0, 0],
-                [0, 0, 0, 0, 0],
-            ],
-            ndarray::array![
-                [0, 0, 0, 0, 0],
-                [0, 0, 0, 0, 0],
-                [0, 1, 1, 1, 0],
-                [0, 0, 1, 0, 0],
-                [0, 0, 1, 0, 0],
-            ],
-        ];
+    /// Simulation time in seconds, advancing by the current step interval with every step.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    /// Current point on the escalation schedule.
+    pub fn escalation(&self) -> Escalation {
+        self.schedule.at(self.elapsed)
+    }
+
+    pub fn query_shape_avg(&self, shape: Shape) -> u8 {
+        self.query_shape_stats(shape).average
+    }
+
+    pub fn query_shape_stats(&self, shape: Shape) -> ShapeStats {
+        let mut sum: usize = 0;
+        let mut max = CLEAN;
+        let mut count: usize = 0;
+        let mut blighted: usize = 0;
+        let mut weighted_damage = 0.0;
+        let (rows, cols) = shape.bounds(self.data_read.blight.dim());
+        let offset = [rows.start, cols.start];
+        let window = s![rows, cols];
+        ndarray::Zip::indexed(self.data_read.blight.slice(window))
+            .and(self.data_read.strain.slice(window))
+            .for_each(|(i, j), value, strain| {
+                if shape.falloff([offset[0] + i, offset[1] + j]).is_some() {
+                    sum += *value as usize;
+                    max = max.max(*value);
+                    count += 1;
+                    if *value != CLEAN {
+                        blighted += 1;
+                        weighted_damage += *value as f32 * Strain::get(*strain).damage_multiplier;
+                    }
+                }
+            });
+
+        if count == 0 {
+            // Shape outside of the grid
+            return ShapeStats {
+                damage_multiplier: 1.0,
+                ..ShapeStats::default()
+            };
+        }
+
+        ShapeStats {
+            average: (sum / count) as u8,
+            max,
+            blighted_fraction: blighted as f32 / count as f32,
+            damage_multiplier: if sum > 0 {
+                weighted_damage / sum as f32
+            } else {
+                1.0
+            },
+        }
+    }
 
+    /// Static noise pattern deciding which kernel each cell uses.
+    fn kernel_noise() -> Array2<f64> {
         let noise = Perlin::new();
+        Array2::from_shape_fn((Self::WIDTH, Self::HEIGHT), |(i, j)| {
+            noise.get([
+                i as f64 / Self::HEIGHT as f64 * 50.0,
+                j as f64 / Self::WIDTH as f64 * 50.0,
+            ])
+        })
+    }
+
+    /// Spreads one strain; cells take as many dilation steps as `steps_by_biome` says for their biome.
+    /// Blight only spreads into a cell behind a barrier on some steps, the fewer the stronger it is.
+    fn do_dilate(
+        kernel_noise: &Array2<f64>,
+        layers: &mut Layers,
+        strain_id: StrainId,
+        steps_by_biome: [usize; Biome::ALL.len()],
+        rng: &mut impl Rng,
+    ) {
+        let strain = Strain::get(strain_id);
+        let max_steps = steps_by_biome.iter().copied().max().unwrap_or(0);
+        for step in 0..max_steps {
+            let source = ndarray::Zip::from(&layers.blight)
+                .and(&layers.strain)
+                .map_collect(|value, owner| if *owner == strain_id { *value } else { CLEAN });
+            if source.iter().all(|value| *value == CLEAN) {
+                break;
+            }
+
+            let dilated = Self::dilate_once(kernel_noise, &source, strain.spread_rule);
+            ndarray::Zip::from(&mut layers.blight)
+                .and(&mut layers.strain)
+                .and(&dilated)
+                .and(&layers.biome)
+                .and(&layers.barrier)
+                .for_each(|value, owner, dilated, biome, barrier| {
+                    if steps_by_biome[Biome::from_value(*biome).index()] <= step {
+                        return;
+                    }
+                    let mut takes_over = if *owner == strain_id || *value == CLEAN {
+                        *dilated > *value
+                    } else {
+                        // Contested cell: compare blight weighted by strength
+                        *value < BLIGHT
+                            && *dilated as f32 * strain.strength
+                                > *value as f32 * Strain::get(*owner).strength
+                    };
+                    if takes_over && *barrier > 0 {
+                        takes_over = rng.gen_range(0..u8::MAX) >= *barrier;
+                    }
+
+                    if takes_over {
+                        *value = *dilated;
+                        *owner = strain_id;
+                    }
+                });
+        }
+    }
+
+    fn dilate_once(kernel_noise: &Array2<f64>, data: &Array2<u8>, rule: SpreadRule) -> Array2<u8> {
+        let mut new_data = Array2::zeros(data.raw_dim());
+        let kernels = rule.kernels();
 
         ndarray::Zip::from(new_data.slice_mut(s![2..Self::WIDTH - 2, 2..Self::HEIGHT - 2]))
-            .and(ijs.slice(s![2..Self::WIDTH - 2, 2..Self::HEIGHT - 2]))
-            .and(data_write.windows((5, 5)))
-            .for_each(|v: &mut u8, (i, j), window| {
-                let noise = noise.get([
-                    i / Self::HEIGHT as f64 * 50.0,
-                    j / Self::WIDTH as f64 * 50.0,
-                ]);
-                let noise_norm = noise;
-                let kernel_idx = (noise_norm * kernels.len() as f64) as usize;
-
-                //let kernel_idx = rng.sample(dist);
-
-                if *v < BLIGHT {
-                    *v = ndarray::Zip::from(window)
-                        .and(&kernels[kernel_idx.clamp(0, kernels.len() - 1)])
-                        .fold(CLEAN, |acc, val, k| acc.max(*val * k));
-                }
+            .and(kernel_noise.slice(s![2..Self::WIDTH - 2, 2..Self::HEIGHT - 2]))
+            .and(data.windows((5, 5)))
+            .for_each(|v: &mut u8, noise, window| {
+                let kernel_idx = (noise * kernels.len() as f64) as usize;
+
+                *v = ndarray::Zip::from(window)
+                    .and(&kernels[kernel_idx.clamp(0, kernels.len() - 1)])
+                    .fold(CLEAN, |acc, val, k| acc.max(*val * k));
             });
-        *data_write = new_data;
+        new_data
     }
 
     pub fn data(&self) -> &Array2<u8> {
+        &self.data_read.blight
+    }
+
+    pub fn layers(&self) -> &Layers {
         &self.data_read
     }
 
     pub fn swap_if_ready(&mut self) {
-        if let Ok(array) = self.outputs_receiver.try_recv() {
-            self.data_read = array;
-            self.shapes_sender
-                .send(std::mem::take(&mut self.shapes))
+        if let Ok((layers, elapsed)) = self.outputs_receiver.try_recv() {
+            self.data_read = layers;
+            self.elapsed = elapsed;
+            self.commands_sender
+                .send(std::mem::take(&mut self.commands))
                 .unwrap();
         }
     }
@@ -215,3 +565,55 @@ impl Default for TerrainArray {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn barrier_holds_back_blight() {
+        // Blight spreads from the left edge towards a wall in columns 100 to 105, split into bands
+        // of rows with a different barrier strength each
+        let strengths = [0, 128, 240, u8::MAX];
+        let band = TerrainArray::HEIGHT / strengths.len();
+        let mut layers = Layers::new(Array2::from_elem(
+            (TerrainArray::WIDTH, TerrainArray::HEIGHT),
+            Biome::ALL[0].splat_value(),
+        ));
+        layers.blight.slice_mut(s![.., ..96]).fill(BLIGHT);
+        for (index, strength) in strengths.iter().enumerate() {
+            layers
+                .barrier
+                .slice_mut(s![index * band..(index + 1) * band, 100..106])
+                .fill(*strength);
+        }
+
+        TerrainArray::do_dilate(
+            &TerrainArray::kernel_noise(),
+            &mut layers,
+            ORIGINAL_STRAIN,
+            Biome::ALL.map(|_| 15),
+            &mut StdRng::seed_from_u64(7),
+        );
+
+        // Rows far enough from the other bands not to be reached around the wall
+        let margin = 32;
+        let blighted: Vec<usize> = (0..strengths.len())
+            .map(|index| {
+                let rows = index * band + margin..(index + 1) * band - margin;
+                layers
+                    .blight
+                    .slice(s![rows, 100..])
+                    .iter()
+                    .filter(|value| **value != CLEAN)
+                    .count()
+            })
+            .collect();
+
+        assert!(blighted[0] > blighted[1], "{blighted:?}");
+        assert!(blighted[1] > blighted[2], "{blighted:?}");
+        assert_eq!(blighted[3], 0);
+    }
+}
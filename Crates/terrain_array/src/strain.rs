@@ -0,0 +1,107 @@
+use ndarray::Array2;
+
+/// Index into [`STRAINS`], stored per cell in the strain layer.
+pub type StrainId = u8;
+
+/// How a strain grows into neighbouring cells.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum SpreadRule {
+    /// Long, noise-directed arms reaching 2 cells per step
+    Tendrils,
+    /// Uniform growth into the 4 direct neighbours
+    Creeping,
+}
+
+impl SpreadRule {
+    /// Dilation kernels; cells pick one based on a static noise pattern.
+    pub fn kernels(self) -> Vec<Array2<u8>> {
+        match self {
+            SpreadRule::Tendrils => vec![
+                ndarray::array![
+                    [0, 0, 1, 0, 0],
+                    [0, 0, 1, 0, 0],
+                    [0, 1, 1, 1, 0],
+                    [0, 0, 0, 0, 0],
+                    [0, 0, 0, 0, 0],
+                ],
+                ndarray::array![
+                    [0, 0, 0, 0, 0],
+                    [0, 0, 1, 0, 0],
+                    [0, 0, 1, 1, 1],
+                    [0, 0, 1, 0, 0],
+                    [0, 0, 0, 0, 0],
+                ],
+                ndarray::array![
+                    [0, 0, 0, 0, 0],
+                    [0, 0, 1, 0, 0],
+                    [1, 1, 1, 0, 0],
+                    [0, 0, 1, 0, 0],
+                    [0, 0, 0, 0, 0],
+                ],
+                ndarray::array![
+                    [0, 0, 0, 0, 0],
+                    [0, 0, 0, 0, 0],
+                    [0, 1, 1, 1, 0],
+                    [0, 0, 1, 0, 0],
+                    [0, 0, 1, 0, 0],
+                ],
+            ],
+            SpreadRule::Creeping => vec![ndarray::array![
+                [0, 0, 0, 0, 0],
+                [0, 0, 1, 0, 0],
+                [0, 1, 1, 1, 0],
+                [0, 0, 1, 0, 0],
+                [0, 0, 0, 0, 0],
+            ]],
+        }
+    }
+}
+
+/// A kind of blight with its own behaviour.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Strain {
+    pub name: &'static str,
+    pub spread_rule: SpreadRule,
+    /// Dilation steps per simulation step, multiplied with the biome's spread multiplier
+    pub speed: f32,
+    /// Multiplies the damage structures take from this strain
+    pub damage_multiplier: f32,
+    /// Fraction of blight surviving a cleaning pass, combined with the biome's difficulty
+    pub clean_resistance: f32,
+    /// Where strains meet, the stronger one takes over cells that are not fully blighted
+    pub strength: f32,
+}
+
+pub const ORIGINAL_STRAIN: StrainId = 0;
+
+pub const STRAINS: [Strain; 2] = [
+    Strain {
+        name: "Withering",
+        spread_rule: SpreadRule::Tendrils,
+        speed: 1.0,
+        damage_multiplier: 1.0,
+        clean_resistance: 0.2,
+        strength: 2.0,
+    },
+    Strain {
+        name: "Creeper",
+        spread_rule: SpreadRule::Creeping,
+        speed: 2.0,
+        damage_multiplier: 0.6,
+        clean_resistance: 0.0,
+        strength: 1.0,
+    },
+];
+
+impl Strain {
+    pub fn get(id: StrainId) -> &'static Strain {
+        &STRAINS[id as usize]
+    }
+
+    pub fn id_by_name(name: &str) -> Option<StrainId> {
+        STRAINS
+            .iter()
+            .position(|strain| strain.name == name)
+            .map(|index| index as StrainId)
+    }
+}
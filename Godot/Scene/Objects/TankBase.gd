@@ -0,0 +1,5 @@
+extends "res://Scene/Objects/Structure.gd"
+
+# The tank mesh has no tint shader, show the power state on the fill level instead
+func setPowered(powered):
+	$Text.textColor = poweredColor if powered else normalColor
@@ -1,4 +1,45 @@
 extends Control
 
-func set_ore_amount(amount: int): 
-	$OreAmountLabel.text = "Ore amount: " + str(amount)
+var outbreakCountdown: float = 0.0
+var outbreakStrain: String = ""
+var alertingSensors: Dictionary = {}
+
+# Shows every resource that is in use, with the income and expenses of the last period
+func set_balances(balances: Dictionary, income: Dictionary, expense: Dictionary):
+	var parts = []
+	for resource in balances:
+		var amount = balances[resource]
+		if resource != "Ore" and amount == 0 and income[resource] == 0 and expense[resource] == 0:
+			continue
+		parts.append(str(resource, ": ", amount, " (+", income[resource], "/-", expense[resource], ")"))
+
+	$OreAmountLabel.text = PoolStringArray(parts).join("   ")
+
+func set_escalation_level(level: int):
+	$EscalationLabel.text = "Escalation: " + str(level + 1)
+
+func announce_outbreak(strain: String, leadTime: float):
+	outbreakStrain = strain
+	outbreakCountdown = leadTime
+	$OutbreakLabel.visible = true
+
+func set_sensor_alert(sensorId: int, alerting: bool):
+	if alerting:
+		alertingSensors[sensorId] = true
+	else:
+		alertingSensors.erase(sensorId)
+
+func _process(dt: float):
+	if outbreakCountdown > 0.0:
+		outbreakCountdown -= dt
+		$OutbreakLabel.text = str(outbreakStrain, " outbreak in ", ceil(outbreakCountdown), "s!")
+	else:
+		$OutbreakLabel.visible = false
+
+	# Destroyed sensors don't report anymore
+	for sensorId in alertingSensors.keys():
+		if not is_instance_id_valid(sensorId):
+			alertingSensors.erase(sensorId)
+
+	$SensorLabel.visible = !alertingSensors.empty()
+	$SensorLabel.text = str("Blight detected by ", alertingSensors.size(), " sensor(s)")
@@ -7,12 +7,6 @@ const RisingText = preload("res://Scene/Util/RisingText.tscn")
 
 const RAY_LENGTH = 1000.0
 
-const BUILD_RADIUS = 6.0
-
-# FIXME duplicated in structure.rs
-const IRRIGATION_COST = 50
-const PUMP_COST = 15
-
 var matDefault: SpatialMaterial
 var matHighlighted: SpatialMaterial
 var matSelected: SpatialMaterial
@@ -43,6 +37,16 @@ func alignPipe(pipe: Spatial, from: Vector3, to: Vector3) -> void:
 	pipe.look_at(to, Vector3.UP)
 
 
+# Stretches a barrier along the wall segment from..to
+func alignBarrier(barrierId: int, from: Vector3, to: Vector3) -> void:
+	var barrier: Spatial = instance_from_id(barrierId)
+	var dist = from.distance_to(to)
+
+	barrier.transform = Transform().scaled(Vector3(0.2, 0.2, 0.5 * dist))
+	barrier.transform.origin = (from + to) / 2
+	barrier.look_at(to, Vector3.UP)
+
+
 func setPowered(instanceId: int, powered: bool) -> void:
 	#print("setPowered: ", instanceId, " powered=", powered)
 	var obj: Spatial = instance_from_id(instanceId)
@@ -54,6 +58,14 @@ func setPowered(instanceId: int, powered: bool) -> void:
 		#obj.applyMaterial(matDefault)
 
 
+# Applies the power changes reported by SpatialApi, for structures and pipes
+func applyPowerChanges(result) -> void:
+	for id in result.powered_ids:
+		setPowered(id, true)
+	for id in result.unpowered_ids:
+		setPowered(id, false)
+
+
 # ----------------------------------------------------------------------------------------------------------------------------------------------
 
 # Called when the node enters the scene tree for the first time.
@@ -63,10 +75,18 @@ func _ready():
 		Ore = preload("res://Scene/Objects/Ore.tscn"),
 		Pump = preload("res://Scene/Objects/Pump.tscn"),
 		Irrigation = preload("res://Scene/Objects/Irrigation.tscn"),
+		Barrier = preload("res://Scene/Objects/Barrier.tscn"),
+		Tank = preload("res://Scene/Objects/Tank.tscn"),
+		Sensor = preload("res://Scene/Objects/Sensor.tscn"),
 		Pipe = preload("res://Scene/Objects/Pipe.tscn"),
 	}
 
-	$SpatialApi.load(scenes)
+	if not $SpatialApi.load(scenes):
+		# Without the structure catalog, nothing can be built
+		set_process(false)
+		set_process_input(false)
+		get_tree().quit()
+		return
 
 	matDefault = SpatialMaterial.new()
 	matHighlighted = SpatialMaterial.new()
@@ -78,8 +98,6 @@ func _ready():
 	matPowered = SpatialMaterial.new()
 	matPowered.albedo_color = Color.blue
 
-	$SceneUi/BuildRadius.scale = Vector3(BUILD_RADIUS, 1.01, BUILD_RADIUS)
-
 
 func _process(dt: float):
 	# Escape
@@ -96,10 +114,21 @@ func _process(dt: float):
 	for id in result.removed_pipe_ids:
 		var node = instance_from_id(id)
 		node.queue_free()
+	applyPowerChanges(result)
+
+	for outbreak in $SpatialApi.update_outbreaks(dt):
+		$HUD.announce_outbreak(outbreak.strain, outbreak.lead_time)
+
+	$HUD.set_escalation_level($Terrain.get_escalation_level())
+
+	for alert in $SpatialApi.update_sensors():
+		$HUD.set_sensor_alert(alert.sensor_id, alert.alerting)
+		if alert.alerting:
+			Sfx.wrong()
 
 	var amounts = $SpatialApi.update_amounts()
 	if amounts != null:
-		$HUD.set_ore_amount(amounts.total_ore)
+		$HUD.set_balances(amounts.balances, amounts.income, amounts.expense)
 
 		var remain = amounts.remaining_resource_amounts
 		for id in remain:
@@ -122,14 +151,19 @@ func _process(dt: float):
 
 	handleMouseInteraction()
 
+const placeableStructureTypes = ["Pump", "Irrigation", "Tank", "Barrier", "Sensor"]
 var placedStructureType = "Pump"
 
 func _input(event):
 	if event is InputEventMouseButton:
 		if event.is_pressed():
 			if event.button_index == BUTTON_WHEEL_UP or event.button_index == BUTTON_WHEEL_DOWN:
-				placedStructureType = "Pump" if placedStructureType == "Irrigation" else "Irrigation"
+				var step = 1 if event.button_index == BUTTON_WHEEL_UP else -1
+				var count = placeableStructureTypes.size()
+				var idx = placeableStructureTypes.find(placedStructureType)
+				placedStructureType = placeableStructureTypes[(idx + step + count) % count]
 				print(placedStructureType)
+				updateBuildRadius()
 		   
 
 func handleMouseInteraction():      
@@ -152,12 +186,71 @@ func handleMouseInteraction():
 
 	if collider is StaticBody:
 		var hovered: Spatial = collider.get_parent()
-		if $SpatialApi.can_build_from(hovered.get_instance_id()):
-			updateTooltip(hovered, "Left click: select")
-			
-			# Left click selected
-			if Input.is_action_just_pressed("left_click"):
-				updateSelected(hovered)
+		var canBuildFrom = $SpatialApi.can_build_from(hovered.get_instance_id())
+		var tip = "Left click: select" if canBuildFrom else ""
+		var repairCost = $SpatialApi.get_repair_cost(hovered.get_instance_id())
+		if repairCost != null and repairCost > 0:
+			tip += str("\nR: repair (cost ", repairCost, ")")
+		var upgradeCost = $SpatialApi.get_upgrade_cost(hovered.get_instance_id())
+		if upgradeCost != null:
+			tip += str("\nU: upgrade (cost ", upgradeCost, ")")
+		var refund = $SpatialApi.get_demolish_refund(hovered.get_instance_id())
+		if refund != null:
+			tip += str("\nX: demolish (refund ", refund, ")")
+		if selectedObj != null and selectedObj != hovered:
+			if $SpatialApi.find_pipe(selectedObj.get_instance_id(), hovered.get_instance_id()) != null:
+				tip += "\nV: disconnect from selected"
+			else:
+				tip += "\nC: connect to selected"
+		if tip != "":
+			updateTooltip(hovered, tip.strip_edges())
+
+		# Left click selected
+		if canBuildFrom and Input.is_action_just_pressed("left_click"):
+			updateSelected(hovered)
+
+		if Input.is_action_just_pressed("repair"):
+			if $SpatialApi.repair_structure(hovered.get_instance_id()):
+				Sfx.placeItem()
+			else:
+				Sfx.wrong()
+
+		if Input.is_action_just_pressed("upgrade"):
+			if $SpatialApi.upgrade_structure(hovered.get_instance_id()):
+				Sfx.placeItem()
+			else:
+				Sfx.wrong()
+
+		if selectedObj != null and Input.is_action_just_pressed("connect"):
+			var connected = $SpatialApi.connect_structures(selectedObj.get_instance_id(), hovered.get_instance_id())
+			if connected != null:
+				applyPowerChanges(connected)
+				Sfx.placeItem()
+			else:
+				Sfx.wrong()
+
+		if selectedObj != null and Input.is_action_just_pressed("disconnect"):
+			var pipeId = $SpatialApi.find_pipe(selectedObj.get_instance_id(), hovered.get_instance_id())
+			var disconnected = null
+			if pipeId != null:
+				disconnected = $SpatialApi.remove_pipe(pipeId)
+			if disconnected != null:
+				for id in disconnected.removed_pipe_ids:
+					instance_from_id(id).queue_free()
+				applyPowerChanges(disconnected)
+			else:
+				Sfx.wrong()
+
+		if Input.is_action_just_pressed("demolish"):
+			var removedPipeIds = $SpatialApi.demolish(hovered.get_instance_id())
+			if removedPipeIds != null:
+				for id in removedPipeIds:
+					instance_from_id(id).queue_free()
+				hideGhosts()
+				# The structure is gone, don't highlight it
+				return
+			else:
+				Sfx.wrong()
 
 		# Just hovering (or clicked + hovered)
 		updateHovered(hovered)
@@ -170,15 +263,17 @@ func handleMouseInteraction():
 		$SceneUi/EffectRadius.visible = false
 
 
-		var buildingCost = PUMP_COST if placedStructureType == "Pump" else IRRIGATION_COST
-		var groundPosInRange = null
+		var groundPos = null
+		var placement = null
 		if selectedObj != null:
-			var s = str(placedStructureType, " (cost ", buildingCost, ")")
-			updateTooltip(null, str("Right click: place ", s, "\nScroll wheel: switch building"))
+			groundPos = raycastMouseGround(localMousePos)
+			placement = $SpatialApi.can_place(placedStructureType, groundPos, selectedObj.get_instance_id())
 
-			var groundPos = raycastMouseGround(localMousePos)
-			if groundPos.distance_squared_to(selectedObj.translation) < BUILD_RADIUS * BUILD_RADIUS:
-				groundPosInRange = groundPos
+			var s = str(placedStructureType, " (cost ", placement.cost, ")")
+			var tip = str("Right click: place ", s, "\nScroll wheel: switch building")
+			if not placement.allowed:
+				tip += str("\n", placement.message)
+			updateTooltip(null, tip)
 
 		else:
 			if hasBuilt:
@@ -193,29 +288,33 @@ func handleMouseInteraction():
 			selectedObj = null
 			return
 
-		# Place building
+		# Place building (placement rules are checked again, and ore consumed, by add_structure)
 		if Input.is_action_just_pressed("right_click"):
-			if $SpatialApi.can_consume_ore(buildingCost):
-				$SpatialApi.consume_ore(buildingCost)
-				if groundPosInRange != null:
-					var add = AddStructure.new()
-					add.position = groundPosInRange
-					add.structure_ty = placedStructureType
-					add.pipe_from_obj = selectedObj
-					
-					var id = $SpatialApi.add_structure(add)
-					Sfx.startMachineSound(groundPosInRange, id)
+			if placement != null and placement.allowed:
+				var add = AddStructure.new()
+				add.position = groundPos
+				add.structure_ty = placedStructureType
+				add.pipe_from_obj = selectedObj
+
+				var id = $SpatialApi.add_structure(add)
+				if id == null:
+					Sfx.wrong()
+					return
+
+				if placedStructureType in ["Pump", "Irrigation"]:
+					Sfx.startMachineSound(groundPos, id)
+				if $SpatialApi.can_build_from(id):
 					updateSelected(instance_from_id(id))
-					
-					Sfx.placeItem()
-					hasBuilt = true
+
+				Sfx.placeItem()
+				hasBuilt = true
 			else:
 				Sfx.wrong()
 			return
 
 		# Drag ghost
-		if groundPosInRange != null:
-			showGhosts(selectedObj.translation, groundPosInRange)
+		if placement != null and placement.reason != "OutOfRange":
+			showGhosts(selectedObj.translation, groundPos)
 
 		else:
 			hideGhosts()
@@ -227,6 +326,16 @@ func updateSelected(obj) -> void:
 
 	$SceneUi/BuildRadius.translation = obj.translation
 	$SceneUi/BuildRadius.visible = true
+	updateBuildRadius()
+
+
+# Pipes from pumps reach further, so the radius depends on both structures
+func updateBuildRadius() -> void:
+	if selectedObj == null:
+		return
+
+	var buildRadius = $SpatialApi.get_build_radius(placedStructureType, selectedObj.get_instance_id())
+	$SceneUi/BuildRadius.scale = Vector3(buildRadius, 1.01, buildRadius)
 
 	
 func updateHovered(obj) -> void:
@@ -259,10 +368,16 @@ func hideGhosts() -> void:
 # from: selected pos
 # to:  pos of new building
 func showGhosts(from: Vector3, to: Vector3) -> void:
-	var ghostIdx = 0 if placedStructureType == "Pump" else 1
+	# Only pumps and irrigation have a ghost, structures that can't be powered have no pipe
+	var ghostIdx = ["Pump", "Irrigation"].find(placedStructureType)
 	for i in range(0,2):
 		ghostsStc[i].visible = i == ghostIdx
-	ghostsStc[ghostIdx].translation = to
+	if not $SpatialApi.get_structure_stats(placedStructureType).can_be_powered:
+		ghostPipe.visible = false
+		return
+
+	if ghostIdx != -1:
+		ghostsStc[ghostIdx].translation = to
 
 	ghostPipe.visible = true
 	alignPipe(ghostPipe, from, to)
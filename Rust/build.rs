@@ -37,6 +37,10 @@ fn main() {
 			class!(BlightUpdated),
 			class!(AmountsUpdated),
 			class!(QueryResult),
+			class!(OutbreakAnnounced),
+			class!(SensorAlert),
+			class!(PlacementResult),
+			class!(NetworkUpdated),
 		],
 	};
 
@@ -2,9 +2,20 @@ use gdnative::prelude::*;
 
 #[derive(NativeClass, Debug, Default)]
 pub struct AmountsUpdated {
-	/// Available ore in player's purse
+	/// Amount of every resource in the ledger, e.g. "Ore"
+	/// Type: \[String] -> int
 	#[property]
-	pub total_ore: i32,
+	pub balances: Dictionary,
+
+	/// Earned in the last accounting period, per resource
+	/// Type: \[String] -> int
+	#[property]
+	pub income: Dictionary,
+
+	/// Spent or consumed in the last accounting period, per resource
+	/// Type: \[String] -> int
+	#[property]
+	pub expense: Dictionary,
 
 	/// Map of instance ID (ore structures) to remaining amount in that field
 	/// Type: \[int] -> int
@@ -2,8 +2,26 @@ use gdnative::prelude::*;
 
 #[derive(NativeClass, Debug, Default)]
 pub struct BlightUpdated {
+	/// Pipes broken by the blight or lost with a destroyed structure, to be freed
 	#[property(get = "Self::get_removed_pipe_ids")]
 	pub removed_pipe_ids: Vec<i64>,
+
+	/// Structures and pipes that took damage, mapped to their new health
+	/// Type: \[int] -> float
+	#[property]
+	pub damaged: Dictionary,
+
+	/// Structures destroyed by the blight (already freed)
+	#[property(get = "Self::get_destroyed_ids")]
+	pub destroyed_ids: Vec<i64>,
+
+	/// Structures and pipes that gained power since the last update
+	#[property(get = "Self::get_powered_ids")]
+	pub powered_ids: Vec<i64>,
+
+	/// Structures and pipes that lost power since the last update
+	#[property(get = "Self::get_unpowered_ids")]
+	pub unpowered_ids: Vec<i64>,
 }
 
 #[methods]
@@ -15,4 +33,16 @@ impl BlightUpdated {
 	fn get_removed_pipe_ids(&self, _base: TRef<Reference>) -> VariantArray {
 		VariantArray::from_iter(self.removed_pipe_ids.iter()).into_shared()
 	}
+
+	fn get_destroyed_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.destroyed_ids.iter()).into_shared()
+	}
+
+	fn get_powered_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.powered_ids.iter()).into_shared()
+	}
+
+	fn get_unpowered_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.unpowered_ids.iter()).into_shared()
+	}
 }
@@ -7,6 +7,10 @@ mod add_structure;
 mod blight_updated;
 mod amounts_updated;
 mod query_result;
+mod outbreak_announced;
+mod sensor_alert;
+mod placement_result;
+mod network_updated;
 
 pub use spatial_api::*;
 pub use terrain::*;
@@ -15,6 +19,10 @@ pub use add_structure::*;
 pub use blight_updated::*;
 pub use amounts_updated::*;
 pub use query_result::*;
+pub use outbreak_announced::*;
+pub use sensor_alert::*;
+pub use placement_result::*;
+pub use network_updated::*;
 
 pub fn register_classes(handle: gdnative::init::InitHandle) {
 	handle.add_class::<SpatialApi>();
@@ -24,4 +32,8 @@ pub fn register_classes(handle: gdnative::init::InitHandle) {
 	handle.add_class::<BlightUpdated>();
 	handle.add_class::<AmountsUpdated>();
 	handle.add_class::<QueryResult>();
+	handle.add_class::<OutbreakAnnounced>();
+	handle.add_class::<SensorAlert>();
+	handle.add_class::<PlacementResult>();
+	handle.add_class::<NetworkUpdated>();
 }
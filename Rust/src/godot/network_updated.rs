@@ -0,0 +1,45 @@
+use gdnative::prelude::*;
+
+/// Changes to the pipe network after connecting or disconnecting structures, see
+/// `SpatialApi::connect_structures()` and `SpatialApi::remove_pipe()`.
+#[derive(NativeClass, Debug, Default)]
+pub struct NetworkUpdated {
+	/// Pipes created (already in the scene tree)
+	#[property(get = "Self::get_added_pipe_ids")]
+	pub added_pipe_ids: Vec<i64>,
+
+	/// Pipes removed, to be freed
+	#[property(get = "Self::get_removed_pipe_ids")]
+	pub removed_pipe_ids: Vec<i64>,
+
+	/// Structures and pipes that gained power
+	#[property(get = "Self::get_powered_ids")]
+	pub powered_ids: Vec<i64>,
+
+	/// Structures and pipes that lost power
+	#[property(get = "Self::get_unpowered_ids")]
+	pub unpowered_ids: Vec<i64>,
+}
+
+#[methods]
+impl NetworkUpdated {
+	fn new(_base: &Reference) -> Self {
+		Self::default()
+	}
+
+	fn get_added_pipe_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.added_pipe_ids.iter()).into_shared()
+	}
+
+	fn get_removed_pipe_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.removed_pipe_ids.iter()).into_shared()
+	}
+
+	fn get_powered_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.powered_ids.iter()).into_shared()
+	}
+
+	fn get_unpowered_ids(&self, _base: TRef<Reference>) -> VariantArray {
+		VariantArray::from_iter(self.unpowered_ids.iter()).into_shared()
+	}
+}
@@ -0,0 +1,26 @@
+use gdnative::prelude::*;
+
+/// A blight outbreak that will appear after `lead_time`.
+#[derive(NativeClass, Debug, Default)]
+pub struct OutbreakAnnounced {
+	#[property]
+	pub position: Vector3,
+
+	#[property]
+	pub radius: f32,
+
+	/// Name of the blight strain
+	#[property]
+	pub strain: String,
+
+	/// Seconds until the blight appears
+	#[property]
+	pub lead_time: f32,
+}
+
+#[methods]
+impl OutbreakAnnounced {
+	fn new(_base: &Reference) -> Self {
+		Self::default()
+	}
+}
@@ -0,0 +1,27 @@
+use gdnative::prelude::*;
+
+/// Whether a structure can be placed at some position, see `SpatialApi::can_place()`.
+#[derive(NativeClass, Debug, Default)]
+pub struct PlacementResult {
+	#[property]
+	pub allowed: bool,
+
+	/// Name of the `PlacementError`, empty if allowed
+	#[property]
+	pub reason: String,
+
+	/// Human-readable reason, empty if allowed
+	#[property]
+	pub message: String,
+
+	/// Ore the structure costs
+	#[property]
+	pub cost: i32,
+}
+
+#[methods]
+impl PlacementResult {
+	fn new(_base: &Reference) -> Self {
+		Self::default()
+	}
+}
@@ -0,0 +1,30 @@
+use gdnative::prelude::*;
+
+/// A sensor's blight reading crossed the alert threshold.
+#[derive(NativeClass, Debug, Default)]
+pub struct SensorAlert {
+	#[property]
+	pub sensor_id: i64,
+
+	#[property]
+	pub position: Vector3,
+
+	/// True when blight rose above the threshold, false when it went back below
+	#[property]
+	pub alerting: bool,
+
+	/// Highest blight value within the sensor's radius (0-255)
+	#[property]
+	pub max_blight: i32,
+
+	/// Fraction of the sensor's radius with any blight
+	#[property]
+	pub blighted_fraction: f32,
+}
+
+#[methods]
+impl SensorAlert {
+	fn new(_base: &Reference) -> Self {
+		Self::default()
+	}
+}
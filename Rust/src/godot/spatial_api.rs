@@ -1,44 +1,95 @@
+use gdnative::api::File;
 use gdnative::prelude::*;
 use rand::prelude::*;
 use rstar::{RTree, AABB};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+use terrain_array::{Strain, CLEAN};
 //use std::collections::HashMap;
 
-use crate::godot::{AddStructure, AmountsUpdated, BlightUpdated, QueryResult, Terrain};
-use crate::objects::{Pipe, Structure, StructureType};
-use crate::{Vector2Ext, Vector3Ext};
+use crate::godot::{
+	AddStructure, AmountsUpdated, BlightUpdated, NetworkUpdated, OutbreakAnnounced,
+	PlacementResult, QueryResult, SensorAlert, Terrain,
+};
+use crate::objects::{
+	build_cost, build_radius, Amounts, FlowSpec, Ledger, OutbreakScheduler, Pipe, PipeNetwork,
+	PipeStats, PlacementError, Resource, SensorLog, Structure, StructureCatalog, StructureType,
+	WaterFlow, WaterMeter, BUILD_RADIUS,
+};
+use crate::{MyDisplay, Vector2Ext, Vector3Ext};
 
 const DAMAGE_PER_SECOND: f32 = 80.0;
+/// Damage barriers take when blight covers their whole surrounding
+const BARRIER_DAMAGE_PER_SECOND: f32 = 10.0;
+/// Damage pipes take when blight covers their whole length
+const PIPE_DAMAGE_PER_SECOND: f32 = 30.0;
 const BLIGHT_THRESHOLD: u8 = 200;
-const STRUCTURE_HEALTH: f32 = 100.0;
 
-/// The amount of collected ore per simulation tick when there's an active miner
-const ORE_PER_COLLECTION: i32 = 5;
-
-/// The amount of water used by irrigators each second
-const WATER_SPENT_PER_SECOND: i32 = 1;
+/// Minimum distance between a new structure and existing ones
+const PLACEMENT_CLEARANCE: f32 = 1.0;
 
 /// The frequency, in number of physics frames, after which active miners will
 /// collect ore
 const MINER_TICK_FREQ: usize = 60 * 5;
 const WATER_TICK_FREQ: usize = 60;
+/// The frequency, in number of physics frames, after which the ledger reports income and expenses
+const LEDGER_PERIOD_FREQ: usize = MINER_TICK_FREQ;
 
 // Make sure water doesn't update at same time as ore (animations)
 const WATER_TICK_OFFSET: usize = 30;
 
+/// The frequency, in number of physics frames, after which sensors take a reading
+const SENSOR_TICK_FREQ: usize = 60;
+/// Sensors alert when the blight in their radius exceeds this; lower than `BLIGHT_THRESHOLD`,
+/// so the alert comes before any damage
+const SENSOR_ALERT_THRESHOLD: u8 = 100;
+
+/// The frequency, in number of physics frames, after which resource fields regenerate
+const RESOURCE_TICK_FREQ: usize = 60;
+const RESOURCE_TICK_OFFSET: usize = 15;
+
+/// The frequency, in number of physics frames, after which a new deposit may appear
+const DEPOSIT_SPAWN_FREQ: usize = 60 * 30;
+/// No new deposits appear once there are that many
+const MAX_DEPOSITS: usize = 80;
+/// Minimum distance between a new deposit and any structure
+const DEPOSIT_CLEARANCE: f32 = 3.0;
+/// New deposits only appear where there is no blight within this radius
+const DEPOSIT_CLEAN_RADIUS: f32 = 4.0;
+/// How many random positions are tried before giving up on a deposit
+const DEPOSIT_PLACEMENT_ATTEMPTS: usize = 20;
+/// Types of random deposits, with their relative frequency
+const DEPOSIT_VARIANTS: [StructureType; 4] = [
+	StructureType::Water,
+	StructureType::Ore,
+	StructureType::Ore,
+	StructureType::Ore,
+];
+
+/// Minimum distance between the edge of a random outbreak and any structure
+const OUTBREAK_STRUCTURE_DISTANCE: f32 = 8.0;
+/// Minimum distance between the edge of a random outbreak and existing blight
+const OUTBREAK_BLIGHT_DISTANCE: f32 = 4.0;
+
 #[derive(NativeClass)]
 #[inherit(Spatial)]
 pub struct SpatialApi {
 	rtree: RTree<Structure>,
 	structures_by_id: HashMap<i64, Structure>,
-	/// Maps irrigators to their power source (Water structure)
-	irrigators_by_powering_water: HashMap<i64, i64>,
-	pipes: Vec<Pipe>,
+	/// Water drawn from sources and stored in tanks, for structures in a pipe network
+	water_meters: HashMap<i64, WaterMeter>,
+	pipes: PipeNetwork,
+	outbreaks: OutbreakScheduler,
+	sensors: HashMap<i64, SensorLog>,
+	/// Structures and pipes whose power state changed since it was last reported, with the new
+	/// state. Reported once, by whichever update returns first.
+	power_changes: HashMap<i64, bool>,
+	/// Empty until `load()`
+	catalog: StructureCatalog,
 
 	terrain: Option<Instance<Terrain>>,
 
 	scenes: Dictionary,
-	ore_amount: i32,
+	ledger: Ledger,
 	frame_count: usize,
 }
 
@@ -50,33 +101,48 @@ impl SpatialApi {
 		Self {
 			rtree: RTree::new(),
 			structures_by_id: HashMap::new(),
-			irrigators_by_powering_water: HashMap::new(),
-			pipes: Vec::new(),
+			water_meters: HashMap::new(),
+			pipes: PipeNetwork::new(),
+			outbreaks: OutbreakScheduler::default(),
+			sensors: HashMap::new(),
+			power_changes: HashMap::new(),
+			catalog: StructureCatalog::default(),
 			terrain: None,
 			scenes: Dictionary::new_shared(),
-			ore_amount: 100,
+			ledger: Ledger::new(&[(Resource::Ore, 100)]),
 			frame_count: 0,
 		}
 	}
 
+	/// Loads the structure catalog and places the initial fields. Returns false if the catalog
+	/// can't be loaded, in which case the game can't start.
 	#[export]
-	fn load(&mut self, base: &Spatial, scenes: Dictionary) {
+	fn load(&mut self, base: &Spatial, scenes: Dictionary) -> bool {
+		match Self::load_catalog(StructureCatalog::PATH) {
+			Ok(catalog) => self.catalog = catalog,
+			Err(err) => {
+				godot_error!("Could not load structure catalog: {}", err);
+				return false;
+			}
+		}
 		self.scenes = scenes;
 
 		let mut structures = vec![];
 
 		// Make sure those appear in any case in their specified amounts
-		let mut at_least_available = vec!["Water", "Ore"];
+		let mut at_least_available = vec![StructureType::Water, StructureType::Ore];
 
-		let variants = ["Water", "Ore", "Ore", "Ore"]; //, "Pump", "Irrigation"];
 		for pos in random_positions(50) {
-			let ty_name = if let Some(ty) = at_least_available.pop() {
+			let ty = if let Some(ty) = at_least_available.pop() {
 				ty
 			} else {
-				variants.into_iter().choose(&mut thread_rng()).unwrap()
+				DEPOSIT_VARIANTS
+					.into_iter()
+					.choose(&mut thread_rng())
+					.unwrap()
 			};
 
-			let stc = self.instance_structure(base, pos, ty_name);
+			let stc = self.instance_structure(base, pos, ty);
 
 			structures.push(stc);
 			self.structures_by_id.insert(stc.instance_id(), stc);
@@ -85,10 +151,12 @@ impl SpatialApi {
 		godot_print!("Bulk-add {} structures", structures.len());
 		self.rtree = RTree::bulk_load(structures);
 		self.terrain = Some(base.get_node_as_instance::<Terrain>("../Terrain").claim());
+		self.debug_validate();
+		true
 	}
 
-	fn instance_structure(&self, base: &Spatial, pos: Vector2, ty_name: &str) -> Structure {
-		let (instanced, id) = self.instance_scene(ty_name);
+	fn instance_structure(&self, base: &Spatial, pos: Vector2, ty: StructureType) -> Structure {
+		let (instanced, id) = self.instance_scene(ty.name());
 
 		instanced.set_translation(pos.to_3d());
 		instanced.set_scale(0.2 * Vector3::ONE);
@@ -96,8 +164,17 @@ impl SpatialApi {
 			.unwrap()
 			.add_child(instanced, false);
 
-		let ty = StructureType::from_name(ty_name);
-		Structure::new(ty, pos, id, STRUCTURE_HEALTH)
+		Structure::new(ty, pos, id, self.catalog.stats(ty))
+	}
+
+	fn load_catalog(path: &str) -> Result<StructureCatalog, String> {
+		let file = File::new();
+		file.open(path, File::READ)
+			.map_err(|err| format!("{}: {:?}", path, err))?;
+		let text = file.get_as_text().to_string();
+		file.close();
+
+		StructureCatalog::from_ron(&text).map_err(|err| format!("{}: {}", path, err))
 	}
 
 	fn instance_pipe(&self, base: &Spatial, from: Vector3, to: Vector3) -> i64 {
@@ -106,8 +183,10 @@ impl SpatialApi {
 		// Important: add to tree first!
 		base.get_node("Pipes").unwrap().add_child(pipe, false);
 
+		// Pipes start dry, and are only reported again when that changes
 		let world = base.get_parent().unwrap();
 		world.call("alignPipe", &v![pipe, from, to]);
+		world.call("setPowered", &v![id, false]);
 		id
 	}
 
@@ -128,14 +207,15 @@ impl SpatialApi {
 	}
 
 	#[export]
-	fn update_blight(&mut self, base: &Spatial, dt: f32) -> Instance<BlightUpdated> {
-		let result = if let Some(inst) = self.terrain.as_mut() {
+	fn update_blight(&mut self, _base: &Spatial, dt: f32) -> Instance<BlightUpdated> {
+		let mut result = if let Some(inst) = self.terrain.as_mut() {
 			inst.map_mut(|terrain, _| {
 				Self::update_blight_impl(
 					&mut self.rtree,
 					&mut self.pipes,
 					&mut self.structures_by_id,
-					&mut self.irrigators_by_powering_water,
+					&mut self.water_meters,
+					&mut self.ledger,
 					dt,
 					terrain,
 				)
@@ -146,9 +226,12 @@ impl SpatialApi {
 		};
 
 		if !result.removed_pipe_ids.is_empty() {
-			self.update_pipe_network(base);
+			self.update_pipe_network();
 		}
 
+		(result.powered_ids, result.unpowered_ids) = self.take_power_changes();
+
+		self.debug_validate();
 		Instance::emplace(result).into_shared()
 	}
 
@@ -157,30 +240,51 @@ impl SpatialApi {
 	#[allow(unreachable_code)]
 	fn update_blight_impl(
 		rtree: &mut RTree<Structure>,
-		pipes: &mut Vec<Pipe>,
+		pipes: &mut PipeNetwork,
 		structures_by_id: &mut HashMap<i64, Structure>,
-		irrigators_by_powering_water: &mut HashMap<i64, i64>,
+		water_meters: &mut HashMap<i64, WaterMeter>,
+		ledger: &mut Ledger,
 		dt: f32,
 		terrain: &mut Terrain,
 	) -> BlightUpdated {
 		let mut structures_to_remove = vec![];
+		let damaged = Dictionary::new();
 
 		for stc in rtree.iter_mut() {
 			profiling::scope!("blight");
 
 			if stc.is_powered() {
-				if let Some(radius) = stc.clean_radius() {
+				if let Some(radius) = stc.effective_clean_radius() {
 					terrain.clean_circle(stc.position().to_3d(), radius);
 				}
 			}
 
 			if let Some(damage_radius) = stc.damage_radius() {
-				let blight =
-					terrain.get_average_blight_in_circle(stc.position().to_3d(), damage_radius);
+				let blight = match stc.segment() {
+					Some([from, to]) => {
+						terrain.get_blight_stats_in_capsule(from.to_3d(), to.to_3d(), damage_radius)
+					}
+					None => {
+						terrain.get_blight_stats_in_circle(stc.position().to_3d(), damage_radius)
+					}
+				};
 
-				if blight > BLIGHT_THRESHOLD {
-					let damage = dt * DAMAGE_PER_SECOND * blight as f32 / 256.0;
-					stc.deal_damage(damage);
+				// Barriers hold the blight back, so they take damage as soon as it touches them
+				let damage = if stc.barrier().is_some() {
+					(blight.max > BLIGHT_THRESHOLD)
+						.then(|| dt * BARRIER_DAMAGE_PER_SECOND * blight.blighted_fraction)
+				} else {
+					(blight.average > BLIGHT_THRESHOLD)
+						.then(|| dt * DAMAGE_PER_SECOND * blight.average as f32 / 256.0)
+				};
+
+				if let Some(damage) = damage {
+					stc.deal_damage(damage * blight.damage_multiplier);
+					Self::sync_structure(*stc, structures_by_id);
+					damaged.insert(stc.instance_id(), stc.health());
+				} else if blight.blighted_fraction == 0.0 && stc.health() < stc.max_health() {
+					stc.heal(dt * stc.regeneration());
+					Self::sync_structure(*stc, structures_by_id);
 				}
 			}
 
@@ -189,29 +293,173 @@ impl SpatialApi {
 			}
 		}
 
-		let removed_pipe_ids = Self::remove_structures_qualified(
+		// Blight along a pipe wears it down; broken pipes split their network
+		let mut broken_pipe_ids = vec![];
+		for pipe in pipes.pipes_mut() {
+			profiling::scope!("pipe blight");
+
+			let [from, to] = pipe.segment();
+			let blight =
+				terrain.get_blight_stats_in_capsule(from.to_3d(), to.to_3d(), pipe.damage_radius());
+
+			if blight.max > BLIGHT_THRESHOLD {
+				let damage = dt * PIPE_DAMAGE_PER_SECOND * blight.blighted_fraction;
+				pipe.deal_damage(damage * blight.damage_multiplier);
+				damaged.insert(pipe.pipe_node_id(), pipe.health());
+			} else if blight.blighted_fraction == 0.0 && pipe.health() < pipe.max_health() {
+				pipe.heal(dt * pipe.regeneration());
+			}
+
+			if !pipe.is_alive() {
+				broken_pipe_ids.push(pipe.pipe_node_id());
+			}
+		}
+		for pipe_id in broken_pipe_ids.iter() {
+			pipes.remove_pipe(*pipe_id);
+		}
+
+		let destroyed_ids = structures_to_remove
+			.iter()
+			.map(|stc| stc.instance_id())
+			.collect();
+
+		let mut removed_pipe_ids = broken_pipe_ids;
+		removed_pipe_ids.extend(Self::remove_structures_qualified(
 			structures_to_remove,
 			rtree,
 			pipes,
 			structures_by_id,
-			irrigators_by_powering_water,
-		);
+			water_meters,
+			terrain,
+			ledger,
+		));
+
+		BlightUpdated {
+			removed_pipe_ids,
+			damaged: damaged.into_shared(),
+			destroyed_ids,
+			..Default::default()
+		}
+	}
+
+	/// Announces new outbreaks and spawns the blight of those whose lead time is over.
+	/// Returns the outbreaks announced in this frame, as `OutbreakAnnounced` objects.
+	#[export]
+	fn update_outbreaks(&mut self, _base: &Spatial, dt: f32) -> VariantArray {
+		let announced = if let Some(inst) = self.terrain.as_mut() {
+			inst.map_mut(|terrain, _| {
+				let rtree = &self.rtree;
+				let (announced, due) =
+					self.outbreaks
+						.update(dt, &terrain.escalation(), |position, radius| {
+							let blight = terrain.get_blight_stats_in_circle(
+								position.to_3d(),
+								radius + OUTBREAK_BLIGHT_DISTANCE,
+							);
+							blight.max == CLEAN
+								&& !Self::has_structure_in_radius(
+									rtree,
+									position,
+									radius + OUTBREAK_STRUCTURE_DISTANCE,
+								)
+						});
+
+				for outbreak in due {
+					terrain.spawn_strain_circle(
+						outbreak.position.to_3d(),
+						outbreak.radius,
+						outbreak.strain,
+					);
+				}
+				announced
+			})
+			.unwrap()
+		} else {
+			vec![]
+		};
+
+		announced
+			.into_iter()
+			.map(|outbreak| {
+				godot_print!("Outbreak announced at {}", outbreak.position.str());
+				let result = OutbreakAnnounced {
+					position: outbreak.position.to_3d(),
+					radius: outbreak.radius,
+					strain: Strain::get(outbreak.strain).name.to_string(),
+					lead_time: outbreak.time_left,
+				};
+				Instance::emplace(result).into_shared()
+			})
+			.collect::<VariantArray<Unique>>()
+			.into_shared()
+	}
+
+	/// Schedules the blight of an outbreak to appear `time` seconds after the start of the run, at
+	/// a random position. It is announced a few seconds earlier.
+	#[export]
+	fn schedule_outbreak(&mut self, _base: &Spatial, time: f32, radius: f32, strain: String) {
+		self.schedule_outbreak_impl(time, None, radius, &strain);
+	}
+
+	/// Like `schedule_outbreak()`, but at the given position. The outbreak is skipped if the
+	/// position is too close to structures or blight, like random ones would be.
+	#[export]
+	fn schedule_outbreak_at(
+		&mut self,
+		_base: &Spatial,
+		time: f32,
+		position: Vector3,
+		radius: f32,
+		strain: String,
+	) {
+		self.schedule_outbreak_impl(time, Some(position.to_2d()), radius, &strain);
+	}
+
+	fn schedule_outbreak_impl(
+		&mut self,
+		time: f32,
+		position: Option<Vector2>,
+		radius: f32,
+		strain: &str,
+	) {
+		match Strain::id_by_name(strain) {
+			Some(strain) => self.outbreaks.schedule(time, position, radius, strain),
+			None => godot_error!("Invalid blight strain '{}'", strain),
+		}
+	}
 
-		BlightUpdated { removed_pipe_ids }
+	fn has_structure_in_radius(rtree: &RTree<Structure>, center: Vector2, radius: f32) -> bool {
+		let half_size = Vector2::ONE * radius;
+		let p1 = (center - half_size).to_rstar();
+		let p2 = (center + half_size).to_rstar();
+
+		let aabb = AABB::from_corners(p1, p2);
+		let radius_sq = radius * radius;
+		rtree
+			.locate_in_envelope(&aabb)
+			.any(|stc| stc.position().distance_squared_to(center) < radius_sq)
 	}
 
 	/// Removes structures, updating refs
 	fn remove_structures_qualified(
 		structures_to_remove: Vec<Structure>,
 		rtree: &mut RTree<Structure>,
-		pipes: &mut Vec<Pipe>,
+		pipes: &mut PipeNetwork,
 		structures_by_id: &mut HashMap<i64, Structure>,
-		irrigators_by_powering_water: &mut HashMap<i64, i64>,
+		water_meters: &mut HashMap<i64, WaterMeter>,
+		terrain: &mut Terrain,
+		ledger: &mut Ledger,
 	) -> Vec<i64> {
 		// Remove destroyed structures
 		let mut removed_pipe_ids = vec![];
 		for elem in structures_to_remove.iter() {
 			let id_to_remove = elem.instance_id();
+			Self::apply_barrier(terrain, elem, 0);
+
+			// Water stored in tanks is lost with them
+			if elem.ty() == StructureType::Tank {
+				ledger.drain(Resource::Water, elem.amount());
+			}
 
 			unsafe {
 				autoload::<Node>("Sfx")
@@ -225,31 +473,9 @@ impl SpatialApi {
 			let node = unsafe { Node::from_instance_id(node_id) };
 			node.queue_free();
 
-			// O(n^2), could be done by Multimap<i64, Structure> (or HashMap<i64, Vec<Structure>>) but likely won't matter in this scale
-			//pipes.retain(|pipe| pipe.start_id() != node_id && pipe.end_id() != node_id);
+			removed_pipe_ids.extend(pipes.remove_node(node_id).iter().map(Pipe::pipe_node_id));
 
-			let mut i = 0;
-			while i < pipes.len() {
-				let pipe = pipes[i];
-				if pipe.start_node_id() == node_id || pipe.end_node_id() == node_id {
-					pipes.swap_remove(i);
-					removed_pipe_ids.push(pipe.pipe_node_id());
-				} else {
-					i += 1;
-				}
-			}
-
-			// Wipe all refs from this map too
-			// Values removal is strictly not necessary (because water can't be removed), but this is sure to blow up in the future
-			let mut keys_to_remove = vec![id_to_remove];
-			for (key, value) in irrigators_by_powering_water.iter_mut() {
-				if *value == id_to_remove {
-					keys_to_remove.push(*key);
-				}
-			}
-			for key in keys_to_remove {
-				irrigators_by_powering_water.remove(&key);
-			}
+			water_meters.remove(&id_to_remove);
 		}
 
 		if !structures_to_remove.is_empty() {
@@ -262,6 +488,67 @@ impl SpatialApi {
 		removed_pipe_ids
 	}
 
+	/// Takes sensor readings; returns the `SensorAlert`s of sensors whose reading crossed the
+	/// alert threshold.
+	#[export]
+	fn update_sensors(&mut self, _base: &Spatial) -> VariantArray {
+		let alerts = VariantArray::new();
+		if self.frame_count % SENSOR_TICK_FREQ != 0 {
+			return alerts.into_shared();
+		}
+
+		let structures_by_id = &self.structures_by_id;
+		self.sensors
+			.retain(|id, _| structures_by_id.contains_key(id));
+
+		let terrain = match self.terrain.as_ref() {
+			Some(terrain) => terrain,
+			None => return alerts.into_shared(),
+		};
+
+		terrain
+			.map(|terrain, _| {
+				for sensor in structures_by_id.values() {
+					let radius = match sensor.sensor_radius() {
+						Some(radius) => radius,
+						None => continue,
+					};
+
+					let position = sensor.position().to_3d();
+					let reading = terrain.get_blight_stats_in_circle(position, radius).into();
+					let log = self.sensors.entry(sensor.instance_id()).or_default();
+
+					if let Some(alerting) = log.record(reading, SENSOR_ALERT_THRESHOLD) {
+						let alert = SensorAlert {
+							sensor_id: sensor.instance_id(),
+							position,
+							alerting,
+							max_blight: reading.max as i32,
+							blighted_fraction: reading.blighted_fraction,
+						};
+						alerts.push(Instance::emplace(alert).into_shared());
+					}
+				}
+			})
+			.unwrap();
+
+		self.debug_validate();
+		alerts.into_shared()
+	}
+
+	/// Fractions of a sensor's radius covered by blight, oldest reading first.
+	/// `None` if the structure is not a sensor or has no readings yet.
+	#[export]
+	fn get_sensor_history(&self, _base: &Spatial, instance_id: i64) -> Option<Float32Array> {
+		let log = self.sensors.get(&instance_id)?;
+		let fractions = log
+			.readings()
+			.map(|reading| reading.blighted_fraction)
+			.collect();
+
+		Some(Float32Array::from_vec(fractions))
+	}
+
 	#[export]
 	fn update_amounts(&mut self, base: &Spatial) -> Option<Instance<AmountsUpdated>> {
 		let remaining_resource_amounts = Dictionary::new();
@@ -283,18 +570,53 @@ impl SpatialApi {
 			&mut animated_strings,
 		);
 
-		let updated_water = match updated_water {
-			WaterResult::NothingToDo => false,
-			WaterResult::WaterConsumed => true,
-			WaterResult::WaterDepleted => {
-				self.update_pipe_network(base);
-				true
+		let regenerated = self.regenerate_fields(&remaining_resource_amounts);
+
+		let spawned = self.spawn_deposit(base);
+		if let Some(deposit) = spawned {
+			godot_print!(
+				"New {} deposit at {}",
+				deposit.ty_name(),
+				deposit.position().str()
+			);
+			remaining_resource_amounts.insert(deposit.instance_id(), deposit.amount());
+		}
+
+		// Depleted fields no longer power anything, refilled ones can again
+		let network_changed = matches!(updated_water, WaterResult::WaterDepleted)
+			|| matches!(
+				regenerated,
+				Some(FieldsRegenerated {
+					refilled_water: true
+				})
+			);
+		if network_changed {
+			self.update_pipe_network();
+		}
+
+		let updated_water = !matches!(updated_water, WaterResult::NothingToDo);
+		let updated_fields = regenerated.is_some() || spawned.is_some();
+
+		let closed_period = self.frame_count % LEDGER_PERIOD_FREQ == 0;
+		if closed_period {
+			self.ledger.end_period();
+		}
+
+		self.debug_validate();
+		if updated_mines || updated_water || updated_fields || closed_period {
+			let balances = Dictionary::new();
+			let income = Dictionary::new();
+			let expense = Dictionary::new();
+			for resource in Resource::ALL {
+				balances.insert(resource.name(), self.ledger.balance(resource));
+				income.insert(resource.name(), self.ledger.last_income(resource));
+				expense.insert(resource.name(), self.ledger.last_expense(resource));
 			}
-		};
 
-		if updated_mines || updated_water {
 			let result = AmountsUpdated {
-				total_ore: self.ore_amount,
+				balances: balances.into_shared(),
+				income: income.into_shared(),
+				expense: expense.into_shared(),
 				remaining_resource_amounts: remaining_resource_amounts.into_shared(),
 				animated_positions,
 				animated_diffs,
@@ -318,59 +640,58 @@ impl SpatialApi {
 			return WaterResult::NothingToDo;
 		}
 
-		// Changed in RTree, needs HashMap update
-		let mut structures_to_sync = vec![];
-
+		let dt = WATER_TICK_FREQ as f32 / 60.0;
 		let mut result = WaterResult::WaterConsumed;
 
-		// Trace back each irrigator to its water source
-		for irrigator in self
-			.structures_by_id
-			.values()
-			.filter(|stc| Self::is_powered_irrigator(stc))
-		{
-			let water_id = self
-				.irrigators_by_powering_water
-				.get(&irrigator.instance_id())
-				.expect("Irrigator powered without water source");
-
-			// TODO again O(n^2)
-			// the RTree should only store position + ID (they don't change)
-			// all mutable attributes should be exclusively in the HashMap
-			// let water_stc = self.structures_by_id.get_mut(water_id).unwrap();
-			let mut water_in_rtree = None;
-			for stc in self.rtree.iter_mut() {
-				if stc.instance_id() == *water_id {
-					water_in_rtree = Some(stc);
-					break;
-				}
+		let mut ids: Vec<i64> = self.water_meters.keys().copied().collect();
+		ids.sort_unstable();
+
+		let (mut from_fields, mut from_tanks, mut stored) = (0, 0, 0);
+		for id in ids {
+			let amount = self.water_meters.get_mut(&id).unwrap().advance(dt);
+			if amount == 0 {
+				continue;
 			}
-			let water_in_rtree = water_in_rtree.expect("water field not stored in RTree");
 
-			let water_consumed = water_in_rtree.mine_amount(WATER_SPENT_PER_SECOND);
-			structures_to_sync.push(*water_in_rtree);
+			// TODO the RTree should only store position + ID (they don't change)
+			// all mutable attributes should be exclusively in the HashMap
+			let stc = Self::find_in_rtree(&mut self.rtree, &self.structures_by_id, id);
+			let moved = if amount < 0 {
+				-stc.mine_amount(-amount)
+			} else {
+				stc.store_amount(amount)
+			};
+			let stc = *stc;
+			Self::sync_structure(stc, &mut self.structures_by_id);
 
-			if water_consumed != 0 {
-				animated_positions.push(water_in_rtree.position());
-				animated_diffs.push(-water_consumed);
-				animated_strings.push("Water".into());
+			match stc.ty() {
+				_ if moved > 0 => stored += moved,
+				StructureType::Water => from_fields -= moved,
+				_ => from_tanks -= moved,
+			}
 
-				animated_positions.push(irrigator.position());
-				animated_diffs.push(water_consumed);
+			if moved != 0 {
+				animated_positions.push(stc.position());
+				animated_diffs.push(moved);
 				animated_strings.push("Water".into());
-			} else {
+			}
+
+			// Dry sources and full tanks change the flow of their network
+			if stc.amount() == 0 || (moved > 0 && Some(stc.amount()) == stc.capacity()) {
+				self.pipes.mark_dirty(id);
 				result = WaterResult::WaterDepleted;
 			}
 		}
 
-		for water_in_rtree in structures_to_sync {
-			Self::sync_structure(water_in_rtree, &mut self.structures_by_id);
-		}
+		// Water from fields passes through the ledger, water from tanks was booked when stored
+		self.ledger.earn(&[(Resource::Water, from_fields)]);
+		self.ledger
+			.drain(Resource::Water, from_fields + from_tanks - stored);
 
-		// Iterate connected waters
+		// Iterate connected waters and tanks
 		// TODO could also do changed only
 		for water in self.structures_by_id.values() {
-			if water.ty() != StructureType::Water {
+			if !matches!(water.ty(), StructureType::Water | StructureType::Tank) {
 				continue;
 			}
 
@@ -380,6 +701,92 @@ impl SpatialApi {
 		result
 	}
 
+	/// Looks up the RTree entry of a structure, through its position.
+	fn find_in_rtree<'a>(
+		rtree: &'a mut RTree<Structure>,
+		structures_by_id: &HashMap<i64, Structure>,
+		instance_id: i64,
+	) -> &'a mut Structure {
+		let position = structures_by_id[&instance_id].position().to_rstar();
+		rtree
+			.locate_in_envelope_mut(&AABB::from_point(position))
+			.find(|stc| stc.instance_id() == instance_id)
+			.expect("structure not stored in RTree")
+	}
+
+	/// Lets water and ore fields regain resources. `None` if it's not the time for it.
+	fn regenerate_fields(
+		&mut self,
+		remaining_resource_amounts: &Dictionary<Unique>,
+	) -> Option<FieldsRegenerated> {
+		if (self.frame_count + RESOURCE_TICK_OFFSET) % RESOURCE_TICK_FREQ != 0 {
+			return None;
+		}
+
+		let dt = RESOURCE_TICK_FREQ as f32 / 60.0;
+		let mut refilled_water = false;
+
+		for stc in self.rtree.iter_mut() {
+			if stc.amount_regeneration().is_none() {
+				continue;
+			}
+
+			let regained = stc.regenerate_amount(dt);
+			if regained > 0 {
+				if stc.ty() == StructureType::Water && stc.amount() == regained {
+					self.pipes.mark_dirty(stc.instance_id());
+					refilled_water = true;
+				}
+				remaining_resource_amounts.insert(stc.instance_id(), stc.amount());
+			}
+
+			// Also keeps the fractional progress in sync
+			Self::sync_structure(*stc, &mut self.structures_by_id);
+		}
+
+		Some(FieldsRegenerated { refilled_water })
+	}
+
+	/// Creates a new water or ore field at a random position without blight or structures
+	/// around, from time to time.
+	fn spawn_deposit(&mut self, base: &Spatial) -> Option<Structure> {
+		if self.frame_count % DEPOSIT_SPAWN_FREQ != 0 {
+			return None;
+		}
+
+		let deposit_count = self
+			.structures_by_id
+			.values()
+			.filter(|stc| DEPOSIT_VARIANTS.contains(&stc.ty()))
+			.count();
+		if deposit_count >= MAX_DEPOSITS {
+			return None;
+		}
+
+		let terrain = self.terrain.as_ref()?;
+		let position = random_positions(DEPOSIT_PLACEMENT_ATTEMPTS)
+			.into_iter()
+			.find(|pos| {
+				!Self::has_structure_in_radius(&self.rtree, *pos, DEPOSIT_CLEARANCE)
+					&& terrain
+						.map(|terrain, _| {
+							terrain.get_blight_stats_in_circle(pos.to_3d(), DEPOSIT_CLEAN_RADIUS)
+						})
+						.unwrap()
+						.blighted_fraction == 0.0
+			})?;
+
+		let ty = DEPOSIT_VARIANTS
+			.into_iter()
+			.choose(&mut thread_rng())
+			.unwrap();
+		let stc = self.instance_structure(base, position, ty);
+
+		self.structures_by_id.insert(stc.instance_id(), stc);
+		self.rtree.insert(stc);
+		Some(stc)
+	}
+
 	fn is_powered_irrigator(stc: &Structure) -> bool {
 		stc.ty() == StructureType::Irrigation && stc.is_powered()
 	}
@@ -396,23 +803,27 @@ impl SpatialApi {
 		}
 
 		// Iterate irrigators (skip non-irrigators and inactive ones)
-
-		for irrigator in self
+		let irrigators: Vec<Structure> = self
 			.structures_by_id
 			.values()
 			.filter(|stc| Self::is_powered_irrigator(stc))
-		{
+			.copied()
+			.collect();
+
+		for irrigator in irrigators {
+			let mining_rate = irrigator.mining_rate().unwrap_or(0);
 			let surrounding = Self::iter_structures_in_radius(
 				&mut self.rtree,
 				irrigator.position(),
-				irrigator.clean_radius().unwrap(),
+				irrigator.effective_clean_radius().unwrap(),
 			);
 
 			let mut mined_in_cycle = 0;
 			for stc in surrounding {
 				if stc.ty() == StructureType::Ore {
-					let mined_amount = stc.mine_amount(ORE_PER_COLLECTION);
+					let mined_amount = stc.mine_amount(mining_rate);
 					mined_in_cycle += mined_amount;
+					Self::sync_structure(*stc, &mut self.structures_by_id);
 
 					if mined_amount > 0 {
 						animated_positions.push(stc.position());
@@ -424,7 +835,7 @@ impl SpatialApi {
 				}
 			}
 
-			self.ore_amount += mined_in_cycle;
+			self.ledger.earn(&[(Resource::Ore, mined_in_cycle)]);
 			if mined_in_cycle > 0 {
 				animated_positions.push(irrigator.position());
 				animated_diffs.push(mined_in_cycle);
@@ -488,144 +899,112 @@ impl SpatialApi {
 			.collect()
 	}
 
-	fn update_pipe_network(&mut self, base: &Spatial) {
-		// Note: of course, we could only update from the changed pipes, but this is a more general approach
-		println!("Update pipe network...");
-
-		// First, find all water spots connected to pipes
-		let mut visited_structures = HashSet::<i64>::new();
-		let mut powered_structures = HashMap::<i64, i64>::new(); // powered-stc mapped to original-water-id
-		let mut powered_pipes = HashSet::<i64>::new();
-		let mut graph_roots = Vec::new();
-
-		for pipe in self.pipes.iter() {
-			let pipe_id = pipe.pipe_node_id();
-
-			let start_id = pipe.start_node_id();
-			let start_stc = *self.structures_by_id.get(&start_id).unwrap();
-			if start_stc.ty() == StructureType::Water && start_stc.amount() > 0 {
-				powered_structures.insert(start_id, start_id); // water to itself
-				powered_pipes.insert(pipe_id);
-				graph_roots.push((pipe_id, start_id));
-			}
-
-			let end_id = pipe.end_node_id();
-			let end_stc = *self.structures_by_id.get(&end_id).unwrap();
-			if end_stc.ty() == StructureType::Water && start_stc.amount() > 0 {
-				powered_structures.insert(end_id, end_id); // water to itself
-				powered_pipes.insert(pipe_id);
-				graph_roots.push((pipe_id, end_id));
-			}
-		}
+	/// Computes the water flow in the parts of the network connected to dirty structures again.
+	/// Structures and pipes whose powered state changed are kept in `power_changes`, until the next
+	/// update returning them.
+	fn update_pipe_network(&mut self) {
+		for component in self.pipes.take_dirty_components() {
+			let structures_by_id = &self.structures_by_id;
+			let flow = WaterFlow::compute(&self.pipes, &component, |id| {
+				structures_by_id
+					.get(&id)
+					.map(Self::flow_spec)
+					.unwrap_or_default()
+			});
+
+			for &id in component.iter() {
+				let stc = match self.structures_by_id.get(&id) {
+					Some(stc) => *stc,
+					None => continue,
+				};
+
+				match flow.rates.get(&id) {
+					Some(rate) => self.water_meters.entry(id).or_default().rate = *rate,
+					None => {
+						self.water_meters.remove(&id);
+					}
+				}
 
-		for (pipe_id, water_id) in graph_roots {
-			Self::recurse_pipe_graph(
-				pipe_id,
-				water_id,
-				water_id,
-				&self.pipes,
-				&self.structures_by_id,
-				&mut powered_structures,
-				&mut powered_pipes,
-				&mut visited_structures,
-			);
-		}
+				if !stc.can_be_powered() {
+					continue;
+				}
 
-		// Apply changes to every structure
-		self.irrigators_by_powering_water.clear();
+				let is_powered = flow.powered.contains(&id);
+				let supply = flow.supply.get(&id).copied().unwrap_or(0.0);
+				let supply_changed = stc.water_demand().is_some() && stc.water_supply() != supply;
+				if stc.is_powered() == is_powered && !supply_changed {
+					continue;
+				}
 
-		let world = base.get_parent().unwrap();
-		for stc in self.rtree.iter_mut() {
-			let id = stc.instance_id();
-			let powering_water = powered_structures.get(&id);
+				// Update in 2 places (keep map and rtree in sync)
+				self.modify_structure(stc, |stc| {
+					stc.set_powered(is_powered);
+					if stc.water_demand().is_some() {
+						stc.set_water_supply(supply);
+					}
+				});
 
-			if !stc.can_be_powered() {
-				continue;
+				if stc.is_powered() != is_powered {
+					self.power_changes.insert(id, is_powered);
+				}
 			}
 
-			if let Some(water) = powering_water {
-				self.irrigators_by_powering_water.insert(id, *water);
+			let mut pipe_ids: Vec<i64> = component
+				.iter()
+				.flat_map(|id| self.pipes.neighbours(*id))
+				.map(|(pipe_id, _)| *pipe_id)
+				.collect();
+			pipe_ids.sort_unstable();
+			pipe_ids.dedup();
+
+			for pipe_id in pipe_ids {
+				let is_powered = flow.pipe_ids.contains(&pipe_id);
+				if self.pipes.set_pipe_powered(pipe_id, is_powered) {
+					self.power_changes.insert(pipe_id, is_powered);
+				}
 			}
-
-			// Update in 2 places (keep map and rtree in sync)
-			let powered = powering_water.is_some();
-			stc.set_powered(powered);
-			Self::sync_structure(*stc, &mut self.structures_by_id);
-			world.call("setPowered", &v![stc.instance_id(), powered]);
 		}
-
-		for pipe in self.pipes.iter() {
-			let id = pipe.pipe_node_id();
-			let powered = powered_pipes.contains(&id);
-			world.call("setPowered", &v![id, powered]);
-		}
-
-		//println!("Done updating pipe network.\n");
 	}
 
-	fn recurse_pipe_graph(
-		pipe_id: i64,
-		stc_id: i64,
-		original_water_id: i64, // backtrack where water comes from
-		pipes: &Vec<Pipe>,
-		structures_by_id: &HashMap<i64, Structure>,
-		powered_structures: &mut HashMap<i64, i64>,
-		powered_pipes: &mut HashSet<i64>,
-		visited_structures: &mut HashSet<i64>,
-	) {
-		if visited_structures.contains(&stc_id) {
-			return;
-		}
-		visited_structures.insert(stc_id);
-
-		let stc = structures_by_id.get(&stc_id).unwrap();
-		if stc.can_be_powered() {
-			//println!("    Power {stc_id}!");
-			powered_pipes.insert(pipe_id);
-			powered_structures.insert(stc_id, original_water_id);
-		}
-
-		for (pipe_id, adjacent_id) in
-			Self::get_pipe_adjacent_pairs(stc_id, pipes, visited_structures)
-		{
-			//println!("  Explore pipe {pipe_id}: connects {stc_id} -> {adjacent_id}...");
-			Self::recurse_pipe_graph(
-				pipe_id,
-				adjacent_id,
-				original_water_id,
-				pipes,
-				structures_by_id,
-				powered_structures,
-				powered_pipes,
-				visited_structures,
-			);
-		}
+	/// Takes the structures and pipes that gained and lost power since they were last reported.
+	/// Those removed in the meantime are left out.
+	fn take_power_changes(&mut self) -> (Vec<i64>, Vec<i64>) {
+		let (structures_by_id, pipes) = (&self.structures_by_id, &self.pipes);
+		let (powered, unpowered): (Vec<_>, Vec<_>) = self
+			.power_changes
+			.drain()
+			.filter(|(id, _)| structures_by_id.contains_key(id) || pipes.get(*id).is_some())
+			.partition(|(_, powered)| *powered);
+
+		(
+			powered.into_iter().map(|(id, _)| id).collect(),
+			unpowered.into_iter().map(|(id, _)| id).collect(),
+		)
 	}
 
-	fn get_pipe_adjacent_pairs(
-		node_id: i64,
-		pipes: &Vec<Pipe>,
-		except: &HashSet<i64>,
-	) -> Vec<(i64, i64)> {
-		// Slow, whatever
-		let mut result = vec![];
-		for pipe in pipes {
-			let pipe_id = pipe.pipe_node_id();
-			let start_id = pipe.start_node_id();
-			let end_id = pipe.end_node_id();
+	/// How a structure takes part in the water flow. Fields with water left supply it, tanks
+	/// supply it when the fields can't, and store what isn't needed.
+	fn flow_spec(stc: &Structure) -> FlowSpec {
+		let output = stc.water_output().unwrap_or(0.0);
+		let mut spec = FlowSpec {
+			can_be_powered: stc.can_be_powered(),
+			demand: stc.water_demand().unwrap_or(0.0),
+			..Default::default()
+		};
 
-			if start_id == node_id {
-				if !except.contains(&end_id) {
-					result.push((pipe_id, end_id));
+		match stc.ty() {
+			StructureType::Water if stc.amount() > 0 => spec.output = output,
+			StructureType::Tank => {
+				if stc.amount() > 0 {
+					spec.reserve = output;
 				}
-			} else if end_id == node_id {
-				if !except.contains(&start_id) {
-					result.push((pipe_id, start_id));
+				if Some(stc.amount()) != stc.capacity() {
+					spec.intake = output;
 				}
 			}
+			_ => {}
 		}
-
-		result
+		spec
 	}
 
 	// Synchronize changes from RTRee to HashMap
@@ -634,44 +1013,251 @@ impl SpatialApi {
 		*stc_mut = stc;
 	}
 
+	/// Builds a structure and pays for it. Returns its instance ID, or null if it can't be placed
+	/// there (see `can_place()`).
 	#[export]
-	fn add_structure(&mut self, base: &Spatial, added: Instance<AddStructure>) -> i64 {
+	fn add_structure(&mut self, base: &Spatial, added: Instance<AddStructure>) -> Option<i64> {
 		let added: AddStructure = added.map(|inst, _| inst.clone()).unwrap();
 
-		let stc = self.instance_structure(base, added.position.to_2d(), &added.structure_ty);
+		let ty = match added.structure_ty.parse::<StructureType>() {
+			Ok(ty) => ty,
+			Err(err) => {
+				godot_error!("Cannot add structure: {}", err);
+				return None;
+			}
+		};
+		let from_id = added.pipe_from_obj.map(|from| from.get_instance_id());
+		let cost = match self.check_placement(ty, added.position.to_2d(), from_id) {
+			Ok(cost) => cost,
+			Err(err) => {
+				godot_error!("Cannot place {}: {}", added.structure_ty, err);
+				return None;
+			}
+		};
+		if let Err(err) = self.ledger.spend(&[(Resource::Ore, cost)]) {
+			godot_error!("Cannot pay for {}: {}", added.structure_ty, err);
+			return None;
+		}
+
+		let mut stc = self.instance_structure(base, added.position.to_2d(), ty);
 		godot_print!("Add structure {:?}", stc);
 
-		if let Some(from) = added.pipe_from_obj {
+		if let Some(barrier) = stc.barrier() {
+			// Walls are built perpendicular to the direction they are built from
+			let direction = added
+				.pipe_from_obj
+				.map(|from| stc.position() - from.translation().to_2d())
+				.filter(|direction| direction.length() > 0.0)
+				.unwrap_or(Vector2::new(1.0, 0.0));
+			let normal = direction.normalized();
+			let half = Vector2::new(-normal.y, normal.x) * barrier.length / 2.0;
+			stc = stc.with_segment([stc.position() - half, stc.position() + half]);
+
+			let [from, to] = stc.segment().unwrap();
+			let world = base.get_parent().unwrap();
+			world.call(
+				"alignBarrier",
+				&v![stc.instance_id(), from.to_3d(), to.to_3d()],
+			);
+			self.with_terrain(|terrain| Self::apply_barrier(terrain, &stc, barrier.strength));
+		}
+
+		// Only structures that can be powered are connected to the pipe network
+		let from_id = from_id.unwrap();
+		if let Some(pipe_stats) = self.pipe_stats(ty, &self.structures_by_id[&from_id]) {
+			let from = added.pipe_from_obj.unwrap();
 			let pipe_id = self.instance_pipe(base, from.translation(), added.position);
-			let from_id = from.get_instance_id();
 			let stc_id = stc.instance_id();
 			let to_id = stc_id;
 
-			self.pipes.push(Pipe::new(pipe_id, from_id, to_id));
+			let segment = [from.translation().to_2d(), stc.position()];
+
+			self.pipes
+				.insert(Pipe::new(pipe_id, from_id, to_id, segment, pipe_stats));
+		}
+
+		// Structures start unpowered, and are only reported again when that changes
+		if stc.can_be_powered() {
+			let world = base.get_parent().unwrap();
+			world.call("setPowered", &v![stc.instance_id(), false]);
+			self.pipes.mark_dirty(stc.instance_id());
 		}
 
 		self.structures_by_id.insert(stc.instance_id(), stc);
 		self.rtree.insert(stc);
 
-		self.update_pipe_network(base);
+		self.update_pipe_network();
+		self.debug_validate();
+
+		Some(stc.instance_id())
+	}
+
+	/// Checks whether a structure of the given type can be built at `position`, from the structure
+	/// with ID `from_id` (0 if none).
+	#[export]
+	fn can_place(
+		&self,
+		_base: &Spatial,
+		structure_ty: String,
+		position: Vector3,
+		from_id: i64,
+	) -> Instance<PlacementResult> {
+		let from_id = Some(from_id).filter(|id| *id != 0);
+		let ty = structure_ty.parse::<StructureType>().map_err(|err| {
+			godot_error!("Cannot check placement: {}", err);
+			PlacementError::UnknownType
+		});
+
+		let result = match ty.and_then(|ty| self.check_placement(ty, position.to_2d(), from_id)) {
+			Ok(cost) => PlacementResult {
+				allowed: true,
+				cost,
+				..Default::default()
+			},
+			Err(err) => PlacementResult {
+				allowed: false,
+				reason: err.name().to_string(),
+				message: err.to_string(),
+				cost: ty
+					.ok()
+					.and_then(|ty| self.placement_cost(ty, position.to_2d(), from_id))
+					.unwrap_or(0),
+			},
+		};
 
-		stc.instance_id()
+		Instance::emplace(result).into_shared()
 	}
 
+	/// Maximum distance between a new structure of type `structure_ty` and the one it's built from.
+	/// Pipes from pumps span further than from other structures.
 	#[export]
-	fn can_consume_ore(&mut self, _base: &Spatial, amt: i32) -> bool {
-		self.ore_amount >= amt
+	fn get_build_radius(&self, _base: &Spatial, structure_ty: String, from_id: i64) -> f32 {
+		let ty = structure_ty.parse::<StructureType>();
+		match (ty, self.structures_by_id.get(&from_id)) {
+			(Ok(ty), Some(from)) => self.build_radius(ty, from),
+			_ => BUILD_RADIUS,
+		}
+	}
+
+	fn build_radius(&self, ty: StructureType, from: &Structure) -> f32 {
+		build_radius(self.pipe_stats(ty, from))
+	}
+
+	/// Stats of the pipe connecting a new structure to the one it's built from, if it gets one.
+	fn pipe_stats(&self, ty: StructureType, from: &Structure) -> Option<PipeStats> {
+		from.pipe()
+			.filter(|_| self.catalog.stats(ty).can_be_powered)
+	}
+
+	/// Ore a structure costs when built at `position`, with its pipe from `from_id` if it gets one.
+	/// Where the pipe can't reach, only the structure is counted.
+	fn placement_cost(
+		&self,
+		ty: StructureType,
+		position: Vector2,
+		from_id: Option<i64>,
+	) -> Option<i32> {
+		let stats = self.catalog.stats(ty);
+		from_id
+			.and_then(|id| self.structures_by_id.get(&id))
+			.and_then(|from| {
+				let distance = from.position().distance_to(position);
+				build_cost(&stats, self.pipe_stats(ty, from), distance).ok()
+			})
+			.or(stats.build_cost)
+	}
+
+	/// The rules for building structures, returns the cost if they are all satisfied.
+	fn check_placement(
+		&self,
+		ty: StructureType,
+		position: Vector2,
+		from_id: Option<i64>,
+	) -> Result<i32, PlacementError> {
+		let from = from_id
+			.and_then(|id| self.structures_by_id.get(&id))
+			.filter(|from| Self::is_build_source(from))
+			.ok_or(PlacementError::InvalidSource)?;
+
+		let stats = self.catalog.stats(ty);
+		let distance = from.position().distance_to(position);
+		let cost = build_cost(&stats, self.pipe_stats(ty, from), distance)?;
+
+		if Self::has_structure_in_radius(&self.rtree, position, PLACEMENT_CLEARANCE) {
+			return Err(PlacementError::Overlapping);
+		}
+
+		// Same rule as for damage, so new structures don't start losing health right away
+		if let (Some(damage_radius), Some(terrain)) = (stats.damage_radius, self.terrain.as_ref()) {
+			let blight = terrain
+				.map(|terrain, _| {
+					terrain.get_blight_stats_in_circle(position.to_3d(), damage_radius)
+				})
+				.unwrap();
+
+			if blight.average > BLIGHT_THRESHOLD {
+				return Err(PlacementError::BlightedGround);
+			}
+		}
+
+		if !self.ledger.can_afford(&[(Resource::Ore, cost)]) {
+			return Err(PlacementError::InsufficientOre { cost });
+		}
+
+		Ok(cost)
 	}
 
+	/// Current amount of a resource, e.g. "Ore".
 	#[export]
-	fn consume_ore(&mut self, _base: &Spatial, amt: i32) {
-		self.ore_amount = (self.ore_amount - amt).max(0)
+	fn get_balance(&self, _base: &Spatial, resource: String) -> i32 {
+		match Resource::from_name(&resource) {
+			Some(resource) => self.ledger.balance(resource),
+			None => {
+				godot_error!("Invalid resource '{}'", resource);
+				0
+			}
+		}
+	}
+
+	/// Whether all of a cost can be paid. `cost` maps resource names to amounts.
+	#[export]
+	fn can_afford(&self, _base: &Spatial, cost: Dictionary) -> bool {
+		Self::parse_amounts(&cost).map_or(false, |cost| self.ledger.can_afford(&cost))
+	}
+
+	/// Pays all of a cost, or nothing if any resource is insufficient. `cost` maps resource
+	/// names to amounts.
+	#[export]
+	fn spend(&mut self, _base: &Spatial, cost: Dictionary) -> bool {
+		match Self::parse_amounts(&cost) {
+			Some(cost) => self.ledger.spend(&cost).is_ok(),
+			None => false,
+		}
+	}
+
+	fn parse_amounts(dict: &Dictionary) -> Option<Vec<(Resource, i32)>> {
+		let mut amounts = vec![];
+		for (key, value) in dict.iter() {
+			let name = key.to::<String>().unwrap_or_default();
+			let resource = Resource::from_name(&name);
+			match (resource, value.to::<i32>()) {
+				(Some(resource), Some(amount)) if amount >= 0 => amounts.push((resource, amount)),
+				_ => {
+					godot_error!("Invalid resource amount {:?}: {:?}", key, value);
+					return None;
+				}
+			}
+		}
+		Some(amounts)
 	}
 
 	#[export]
 	fn get_structure_info(&self, _base: &Spatial, instance_id: i64, minimal: bool) -> String {
 		if let Some(stc) = self.structures_by_id.get(&instance_id) {
 			let mut info = format!("{}", stc.ty_name());
+			if stc.value().is_some() {
+				info += &format!(" level {}", stc.level());
+			}
 			if stc.can_be_powered() {
 				if stc.is_powered() {
 					info += " (powered,";
@@ -679,12 +1265,34 @@ impl SpatialApi {
 					info += " (unpowered,";
 				}
 			}
-			if let Some(cost) = stc.build_cost() {
-				info += &format!(" cost {cost})");
+			// Total ore spent on it, not the price of building one more
+			if let Some(value) = stc.value() {
+				if stc.can_be_powered() {
+					info += &format!(" invested {value})");
+				} else {
+					info += &format!(" (invested {value})");
+				}
+			}
+			if stc.damage_radius().is_some() {
+				info += &format!(" health {:.0}/{:.0}", stc.health(), stc.max_health());
+			}
+			if let Some(capacity) = stc.capacity() {
+				let resource = if stc.ty() == StructureType::Ore {
+					"ore"
+				} else {
+					"water"
+				};
+				info += &format!(" {} {}/{}", resource, stc.amount(), capacity);
+			}
+			if stc.water_demand().is_some() && stc.is_powered() {
+				info += &format!(" water {:.0}%", stc.water_supply() * 100.0);
+			}
+			if let Some(reading) = self.sensors.get(&instance_id).and_then(|log| log.latest()) {
+				info += &format!(" blight {:.0}%", reading.blighted_fraction * 100.0);
 			}
 
 			if !minimal {
-				info += stc.ty_description();
+				info += self.catalog.description(stc.ty());
 			}
 
 			info
@@ -693,13 +1301,437 @@ impl SpatialApi {
 		}
 	}
 
+	/// Current health of a structure, 0 if it doesn't exist (anymore).
+	#[export]
+	fn get_health(&self, _base: &Spatial, instance_id: i64) -> f32 {
+		self.structures_by_id
+			.get(&instance_id)
+			.map(|stc| stc.health())
+			.unwrap_or(0.0)
+	}
+
+	/// Health of a structure when fully repaired, 0 if it doesn't exist (anymore).
+	#[export]
+	fn get_max_health(&self, _base: &Spatial, instance_id: i64) -> f32 {
+		self.structures_by_id
+			.get(&instance_id)
+			.map(|stc| stc.max_health())
+			.unwrap_or(0.0)
+	}
+
+	/// Ore needed to fully repair a structure, or null if it can't be repaired.
+	#[export]
+	fn get_repair_cost(&self, _base: &Spatial, instance_id: i64) -> Option<i32> {
+		self.structures_by_id
+			.get(&instance_id)
+			.and_then(|stc| stc.repair_cost())
+	}
+
+	/// Fully repairs a structure, paying with ore. Returns false if there is not enough ore,
+	/// or nothing to repair.
+	#[export]
+	fn repair_structure(&mut self, _base: &Spatial, instance_id: i64) -> bool {
+		let stc = match self.structures_by_id.get(&instance_id) {
+			Some(stc) => *stc,
+			None => return false,
+		};
+
+		let cost: &Amounts = match stc.repair_cost() {
+			Some(cost) if cost > 0 => &[(Resource::Ore, cost)],
+			_ => return false,
+		};
+		if self.ledger.spend(cost).is_err() {
+			return false;
+		}
+
+		self.modify_structure(stc, |stc| stc.heal(stc.max_health()));
+		self.debug_validate();
+
+		true
+	}
+
+	/// Ore returned when demolishing a structure, or null if it can't be demolished.
+	#[export]
+	fn get_demolish_refund(&self, _base: &Spatial, instance_id: i64) -> Option<i32> {
+		self.structures_by_id
+			.get(&instance_id)
+			.and_then(|stc| stc.demolish_refund())
+	}
+
+	/// Removes a structure built by the player along with its pipes, and refunds part of its value.
+	/// Returns the IDs of the removed pipes (to be freed by the caller), or null if the structure
+	/// can't be demolished.
+	#[export]
+	fn demolish(&mut self, _base: &Spatial, instance_id: i64) -> Option<Vec<i64>> {
+		let stc = *self.structures_by_id.get(&instance_id)?;
+		let refund = stc.demolish_refund()?;
+		let terrain = self.terrain.as_ref()?;
+
+		let removed_pipe_ids = terrain
+			.map_mut(|terrain, _| {
+				Self::remove_structures_qualified(
+					vec![stc],
+					&mut self.rtree,
+					&mut self.pipes,
+					&mut self.structures_by_id,
+					&mut self.water_meters,
+					terrain,
+					&mut self.ledger,
+				)
+			})
+			.unwrap();
+
+		self.ledger.earn(&[(Resource::Ore, refund)]);
+		godot_print!("Demolished {} for {} ore", stc.ty_name(), refund);
+
+		self.update_pipe_network();
+		self.debug_validate();
+		Some(removed_pipe_ids)
+	}
+
+	/// The pipe directly connecting two structures, or null if there is none.
+	#[export]
+	fn find_pipe(&self, _base: &Spatial, first_id: i64, second_id: i64) -> Option<i64> {
+		self.pipe_between(first_id, second_id)
+	}
+
+	fn pipe_between(&self, first_id: i64, second_id: i64) -> Option<i64> {
+		self.pipes
+			.neighbours(first_id)
+			.iter()
+			.find(|(_, other_id)| *other_id == second_id)
+			.map(|(pipe_id, _)| *pipe_id)
+	}
+
+	/// Removes a single pipe and refunds half of its cost; the structures it connected stay.
+	/// Returns null (and logs an error) if there is no such pipe.
+	#[export]
+	fn remove_pipe(&mut self, _base: &Spatial, pipe_id: i64) -> Option<Instance<NetworkUpdated>> {
+		let pipe = match self.pipes.remove_pipe(pipe_id) {
+			Some(pipe) => pipe,
+			None => {
+				godot_error!("Cannot remove pipe {}: no such pipe", pipe_id);
+				return None;
+			}
+		};
+
+		let refund = pipe.cost() / 2;
+		self.ledger.earn(&[(Resource::Ore, refund)]);
+		godot_print!("Removed pipe for {} ore", refund);
+
+		self.update_pipe_network();
+		self.debug_validate();
+		Some(self.network_updated(vec![], vec![pipe_id]))
+	}
+
+	/// Connects two existing structures with a pipe built from the first one, paying with ore.
+	/// Returns null (and logs an error) if they can't be connected.
+	#[export]
+	fn connect_structures(
+		&mut self,
+		base: &Spatial,
+		from_id: i64,
+		to_id: i64,
+	) -> Option<Instance<NetworkUpdated>> {
+		let (pipe_stats, cost) = match self.check_connection(from_id, to_id) {
+			Ok(checked) => checked,
+			Err(err) => {
+				godot_error!("Cannot connect structures: {}", err);
+				return None;
+			}
+		};
+		if let Err(err) = self.ledger.spend(&[(Resource::Ore, cost)]) {
+			godot_error!("Cannot pay for pipe: {}", err);
+			return None;
+		}
+
+		let segment = [
+			self.structures_by_id[&from_id].position(),
+			self.structures_by_id[&to_id].position(),
+		];
+		let pipe_id = self.instance_pipe(base, segment[0].to_3d(), segment[1].to_3d());
+		self.pipes
+			.insert(Pipe::new(pipe_id, from_id, to_id, segment, pipe_stats));
+
+		self.update_pipe_network();
+		self.debug_validate();
+		Some(self.network_updated(vec![pipe_id], vec![]))
+	}
+
+	/// The rules for connecting structures, returns the stats and cost of the pipe if they are
+	/// all satisfied.
+	fn check_connection(
+		&self,
+		from_id: i64,
+		to_id: i64,
+	) -> Result<(PipeStats, i32), PlacementError> {
+		let from = self
+			.structures_by_id
+			.get(&from_id)
+			.filter(|from| Self::is_build_source(from))
+			.ok_or(PlacementError::InvalidSource)?;
+		let pipe_stats = from.pipe().ok_or(PlacementError::InvalidSource)?;
+
+		let to = self
+			.structures_by_id
+			.get(&to_id)
+			.filter(|_| to_id != from_id)
+			.filter(|to| to.can_be_powered() || to.ty() == StructureType::Water)
+			.ok_or(PlacementError::InvalidTarget)?;
+
+		if self.pipe_between(from_id, to_id).is_some() {
+			return Err(PlacementError::AlreadyConnected);
+		}
+
+		let length = from.position().distance_to(to.position());
+		if length > pipe_stats.max_span {
+			return Err(PlacementError::OutOfRange);
+		}
+
+		let cost = pipe_stats.cost(length);
+		if !self.ledger.can_afford(&[(Resource::Ore, cost)]) {
+			return Err(PlacementError::InsufficientOre { cost });
+		}
+
+		Ok((pipe_stats, cost))
+	}
+
+	fn network_updated(
+		&mut self,
+		added_pipe_ids: Vec<i64>,
+		removed_pipe_ids: Vec<i64>,
+	) -> Instance<NetworkUpdated> {
+		let (powered_ids, unpowered_ids) = self.take_power_changes();
+		let result = NetworkUpdated {
+			added_pipe_ids,
+			removed_pipe_ids,
+			powered_ids,
+			unpowered_ids,
+		};
+		Instance::emplace(result).into_shared()
+	}
+
+	/// Ore needed to upgrade a structure to the next level, or null if it's at its maximum level.
+	#[export]
+	fn get_upgrade_cost(&self, _base: &Spatial, instance_id: i64) -> Option<i32> {
+		let stc = self.structures_by_id.get(&instance_id)?;
+		let next_stats = self.catalog.level_stats(stc.ty(), stc.level() + 1)?;
+		next_stats.build_cost
+	}
+
+	/// Upgrades a structure to the next level, paying with ore. Returns false if there is not
+	/// enough ore, or it's already at its maximum level.
+	#[export]
+	fn upgrade_structure(&mut self, _base: &Spatial, instance_id: i64) -> bool {
+		let stc = match self.structures_by_id.get(&instance_id) {
+			Some(stc) => *stc,
+			None => return false,
+		};
+
+		let next_stats = match self.catalog.level_stats(stc.ty(), stc.level() + 1) {
+			Some(stats) => stats,
+			None => return false,
+		};
+
+		let cost = next_stats.build_cost.unwrap_or(0);
+		if self.ledger.spend(&[(Resource::Ore, cost)]).is_err() {
+			return false;
+		}
+
+		self.modify_structure(stc, |stc| stc.upgrade(next_stats));
+		if let Some(barrier) = next_stats.barrier {
+			let upgraded = self.structures_by_id[&instance_id];
+			self.with_terrain(|terrain| Self::apply_barrier(terrain, &upgraded, barrier.strength));
+		}
+		godot_print!("Upgraded {} to level {}", stc.ty_name(), stc.level() + 1);
+
+		// Water output and demand may differ between levels
+		self.pipes.mark_dirty(instance_id);
+		self.update_pipe_network();
+		self.debug_validate();
+
+		true
+	}
+
+	/// Updates the terrain's barrier mask under a wall-like structure, 0 removes it.
+	fn apply_barrier(terrain: &mut Terrain, stc: &Structure, strength: u8) {
+		if let (Some([from, to]), Some(barrier)) = (stc.segment(), stc.barrier()) {
+			terrain.set_barrier(
+				stc.instance_id(),
+				from.to_3d(),
+				to.to_3d(),
+				barrier.thickness,
+				strength,
+			);
+		}
+	}
+
+	fn with_terrain(&self, f: impl FnOnce(&mut Terrain)) {
+		if let Some(inst) = self.terrain.as_ref() {
+			inst.map_mut(|terrain, _| f(terrain)).unwrap();
+		}
+	}
+
+	/// Applies a change to a structure, in the RTree and the HashMap.
+	fn modify_structure(&mut self, stc: Structure, modify: impl FnOnce(&mut Structure)) {
+		let stc_in_rtree = self
+			.rtree
+			.locate_in_envelope_mut(&AABB::from_point(stc.position().to_rstar()))
+			.find(|candidate| candidate.instance_id() == stc.instance_id())
+			.expect("structure not stored in RTree");
+
+		modify(stc_in_rtree);
+		Self::sync_structure(*stc_in_rtree, &mut self.structures_by_id);
+	}
+
+	/// Logs every broken invariant, see `validate_invariants()`. Only in debug builds, as it goes
+	/// through all structures and pipes.
+	fn debug_validate(&self) {
+		#[cfg(debug_assertions)]
+		for violation in self.validate_invariants() {
+			godot_error!("Invariant violated: {}", violation);
+		}
+	}
+
+	/// Checks that the RTree, the HashMap, the pipe network, the water meters and the sensor logs
+	/// agree with each other. Returns the violations, described for humans.
+	#[cfg(debug_assertions)]
+	fn validate_invariants(&self) -> Vec<String> {
+		let mut violations = vec![];
+
+		// RTree and HashMap hold the same structures, in the same state
+		if self.rtree.size() != self.structures_by_id.len() {
+			violations.push(format!(
+				"RTree has {} structures, HashMap has {}",
+				self.rtree.size(),
+				self.structures_by_id.len()
+			));
+		}
+		for stc in self.rtree.iter() {
+			let id = stc.instance_id();
+			match self.structures_by_id.get(&id) {
+				Some(in_map) if !in_map.same_state(stc) => {
+					violations.push(format!("structure {id} differs between RTree and HashMap"))
+				}
+				Some(_) => {}
+				None => violations.push(format!(
+					"structure {id} is in the RTree, but not the HashMap"
+				)),
+			}
+		}
+
+		// Pipes connect existing structures
+		violations.extend(self.pipes.validate());
+		for pipe in self.pipes.pipes() {
+			for node_id in [pipe.start_node_id(), pipe.end_node_id()] {
+				if !self.structures_by_id.contains_key(&node_id) {
+					violations.push(format!(
+						"pipe {} is connected to missing structure {node_id}",
+						pipe.pipe_node_id()
+					));
+				}
+			}
+		}
+
+		// Only sources are drained, and only tanks are filled
+		for (id, meter) in self.water_meters.iter() {
+			let ty = self.structures_by_id.get(id).map(|stc| stc.ty());
+			let valid = match ty {
+				Some(StructureType::Tank) => true,
+				Some(StructureType::Water) => meter.rate <= 0.0,
+				_ => false,
+			};
+			if !valid {
+				violations.push(format!(
+					"water meter of {:?} {id} has rate {}",
+					ty, meter.rate
+				));
+			}
+		}
+
+		// Powered structures are connected to a source with water left. Like in `WaterFlow`, a
+		// structure without pipes is a network of its own.
+		let network_ids = self.pipes.component_ids();
+		let network_of = |id: i64| network_ids.get(&id).copied().unwrap_or(id);
+		let live_networks: std::collections::HashSet<i64> = self
+			.structures_by_id
+			.values()
+			.filter(|stc| matches!(stc.ty(), StructureType::Water | StructureType::Tank))
+			.filter(|stc| stc.amount() > 0)
+			.map(|stc| network_of(stc.instance_id()))
+			.collect();
+		for stc in self.structures_by_id.values() {
+			let id = stc.instance_id();
+			if !stc.can_be_powered() {
+				continue;
+			}
+
+			let has_source = live_networks.contains(&network_of(id));
+			if stc.is_powered() && !has_source {
+				violations.push(format!(
+					"{} {id} is powered without a water source",
+					stc.ty_name()
+				));
+			}
+			if !stc.is_powered() && stc.water_demand().is_some() && stc.water_supply() > 0.0 {
+				violations.push(format!("{} {id} gets water while unpowered", stc.ty_name()));
+			}
+		}
+
+		// Logs of removed sensors are dropped at the next reading
+		for id in self.sensors.keys() {
+			if let Some(stc) = self.structures_by_id.get(id) {
+				if stc.ty() != StructureType::Sensor {
+					violations.push(format!("sensor log kept for {} {id}", stc.ty_name()));
+				}
+			}
+		}
+
+		violations
+	}
+
+	/// Returns the catalog entry of a structure type at level 1, e.g. "Pump". Abilities a type doesn't have are null.
+	/// Keys: description, build_cost, health, regeneration, damage_radius, clean_radius, mining_rate,
+	/// initial_amount, can_be_powered, sensor_radius, water_output, water_demand
+	/// Returns null (and logs an error) if there is no such type.
+	#[export]
+	fn get_structure_stats(&self, _base: &Spatial, ty_name: String) -> Option<Dictionary> {
+		let ty = match ty_name.parse::<StructureType>() {
+			Ok(ty) => ty,
+			Err(err) => {
+				godot_error!("Cannot get structure stats: {}", err);
+				return None;
+			}
+		};
+		let stats = self.catalog.stats(ty);
+
+		let dict = Dictionary::new();
+		dict.insert("description", self.catalog.description(ty));
+		dict.insert("build_cost", stats.build_cost);
+		dict.insert("health", stats.health);
+		dict.insert("regeneration", stats.regeneration);
+		dict.insert("damage_radius", stats.damage_radius);
+		dict.insert("clean_radius", stats.clean_radius);
+		dict.insert("mining_rate", stats.mining_rate);
+		dict.insert("initial_amount", stats.initial_amount);
+		dict.insert("can_be_powered", stats.can_be_powered);
+		dict.insert("sensor_radius", stats.sensor_radius);
+		dict.insert("water_output", stats.water_output);
+		dict.insert("water_demand", stats.water_demand);
+		Some(dict.into_shared())
+	}
+
 	#[export]
 	fn can_build_from(&self, _base: &Spatial, instance_id: i64) -> bool {
 		self.structures_by_id
 			.get(&instance_id)
-			.map(|s| s.ty() != StructureType::Ore)
+			.map(Self::is_build_source)
 			.unwrap_or(false)
 	}
+
+	fn is_build_source(stc: &Structure) -> bool {
+		stc.ty() == StructureType::Water || stc.can_be_powered()
+	}
 }
 
 fn random_positions(n: usize) -> Vec<Vector2> {
@@ -719,9 +1751,14 @@ fn random_positions(n: usize) -> Vec<Vector2> {
 
 // ----------------------------------------------------------------------------------------------------------------------------------------------
 
+struct FieldsRegenerated {
+	/// At least one depleted water field has water again, need to repower its network
+	refilled_water: bool,
+}
+
 enum WaterResult {
 	NothingToDo,
 	WaterConsumed,
-	/// If at least one water field depletes, need to recompute pipes
+	/// If a source runs dry or a tank fills up, need to repower its network
 	WaterDepleted,
 }
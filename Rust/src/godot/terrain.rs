@@ -1,9 +1,8 @@
 use gdnative::{
-	api::{ImageTexture, MeshInstance, PlaneMesh, ShaderMaterial},
+	api::{ImageTexture, MeshInstance, PlaneMesh, ResourceLoader, ShaderMaterial},
 	prelude::*,
 };
-use ndarray::s;
-
+use ndarray::Array2;
 use terrain_array::*;
 
 #[derive(NativeClass, Debug)]
@@ -12,6 +11,13 @@ pub struct Terrain {
 	pub mesh: Option<Ref<MeshInstance>>,
 	array: TerrainArray,
 	measurements: PlaneMeasurements,
+	/// Which simulation layer is packed into the R, G, B and A channels of the splatmap
+	splat_channels: [Layer; 4],
+	/// Grayscale image defining the biomes: dark is lush grass, medium is dry mud, bright is rock.
+	/// If empty, biomes are generated procedurally. They are shown through their own texture,
+	/// `Biomemap`, since they don't change during the game.
+	#[property]
+	pub biome_map: String,
 }
 
 #[derive(Debug, Default)]
@@ -36,19 +42,48 @@ impl Terrain {
 			mesh: None,
 			array: TerrainArray::default(),
 			measurements: Default::default(), // Will initialize later
+			splat_channels: [Layer::Blight, Layer::Age, Layer::Moisture, Layer::Cleaned],
+			biome_map: String::new(),
+		}
+	}
+
+	/// Configures which layer ends up in which splatmap channel.
+	/// Valid names are "Blight", "Age", "Moisture", "Cleaned", "Biome" and "Barrier".
+	#[export]
+	fn set_splat_channels(&mut self, _base: &Node, r: String, g: String, b: String, a: String) {
+		let mut channels = self.splat_channels;
+		for (channel, name) in channels.iter_mut().zip([r, g, b, a]) {
+			match Layer::from_name(&name) {
+				Some(layer) => *channel = layer,
+				None => {
+					godot_error!("Invalid splatmap layer '{}'", name);
+					return;
+				}
+			}
 		}
+
+		self.splat_channels = channels;
 	}
 
 	#[profiling::function]
 	fn reload_image(&mut self) {
+		let layers = self.array.layers();
+		let [r, g, b, a] = self.splat_channels.map(|layer| layers.get(layer));
+
+		let mut rgba = Vec::with_capacity(TerrainArray::WIDTH * TerrainArray::HEIGHT * 4);
+		ndarray::Zip::from(r)
+			.and(g)
+			.and(b)
+			.and(a)
+			.for_each(|r, g, b, a| rgba.extend_from_slice(&[*r, *g, *b, *a]));
+
 		let image = Image::new().into_shared();
-		let bytes = ByteArray::from_slice(self.array.data().slice(s![.., ..]).as_slice().unwrap());
 		image.create_from_data(
 			TerrainArray::WIDTH as i64,
 			TerrainArray::HEIGHT as i64,
 			false,
-			Image::FORMAT_L8,
-			bytes,
+			Image::FORMAT_RGBA8,
+			ByteArray::from_vec(rgba),
 		);
 
 		let material = self
@@ -65,6 +100,50 @@ impl Terrain {
 		texture.create_from_image(image, Texture::FLAGS_DEFAULT);
 	}
 
+	/// Sends the biome layer to the `Biomemap` texture of the ground shader.
+	fn upload_biomes(&self, biomes: &Array2<u8>) {
+		let image = Image::new().into_shared();
+		image.create_from_data(
+			TerrainArray::WIDTH as i64,
+			TerrainArray::HEIGHT as i64,
+			false,
+			Image::FORMAT_L8,
+			ByteArray::from_vec(biomes.iter().copied().collect()),
+		);
+
+		let material = self
+			.mesh
+			.unwrap()
+			.get_surface_material(0)
+			.unwrap()
+			.cast::<ShaderMaterial>();
+
+		let texture = material
+			.get_shader_param("Biomemap")
+			.try_to_object::<ImageTexture>()
+			.unwrap();
+		texture.create_from_image(image, Texture::FLAGS_DEFAULT);
+	}
+
+	fn load_biome_map(path: &str) -> Option<Array2<u8>> {
+		let texture = ResourceLoader::godot_singleton().load(path, "Texture", false)?;
+		let image = texture.cast::<Texture>().get_data()?;
+		image.convert(Image::FORMAT_L8);
+		image.resize(
+			TerrainArray::WIDTH as i64,
+			TerrainArray::HEIGHT as i64,
+			Image::INTERPOLATE_NEAREST,
+		);
+
+		let biomes = image
+			.get_data()
+			.to_vec()
+			.into_iter()
+			.map(|value| Biome::from_value(value).splat_value())
+			.collect();
+		Array2::from_shape_vec((TerrainArray::WIDTH, TerrainArray::HEIGHT), biomes).ok()
+	}
+
 	fn compute_measurements(&self) -> PlaneMeasurements {
 		let mesh = self.mesh.unwrap();
 		let plane_size = mesh.mesh().unwrap().cast::<PlaneMesh>().size();
@@ -81,6 +160,22 @@ impl Terrain {
 		let mesh = get_node!(base, "Mesh", MeshInstance);
 		self.mesh = Some(mesh);
 
+		// The simulation thread only picks up new biomes in its next step, so upload them directly
+		let mut biomes = None;
+		if !self.biome_map.is_empty() {
+			biomes = Self::load_biome_map(&self.biome_map);
+			if biomes.is_none() {
+				godot_error!("Could not load biome map '{}'", self.biome_map);
+			}
+		}
+		match biomes {
+			Some(biomes) => {
+				self.upload_biomes(&biomes);
+				self.array.set_biomes(biomes);
+			}
+			None => self.upload_biomes(&self.array.layers().biome),
+		}
+
 		self.array.fill_shape(
 			Shape::Circle {
 				center: [150, 150],
@@ -112,31 +207,181 @@ impl Terrain {
 		[grid.y as usize, grid.x as usize]
 	}
 
-	/// Given a position in using the inner array's coordinates, returns the
-	/// world position of that point.
-	#[allow(dead_code)]
-	fn grid2world(&self, pos: [usize; 2]) -> Vector2 {
-		let posv2 = Vector2::new(pos[1] as f32, pos[0] as f32);
-		let normalized = posv2
-			* Vector2::new(
-				1.0 / TerrainArray::WIDTH as f32,
-				1.0 / TerrainArray::HEIGHT as f32,
-			);
-		(normalized + self.measurements.top_left) * self.measurements.plane_size
+	/// Given a position in using the inner array's coordinates (`[row, column]`,
+	/// possibly fractional), returns the world position of that point.
+	fn grid2world(&self, pos: [f32; 2]) -> Vector2 {
+		let normalized = Vector2::new(
+			pos[1] / TerrainArray::WIDTH as f32,
+			pos[0] / TerrainArray::HEIGHT as f32,
+		);
+		self.measurements.top_left + normalized * self.measurements.plane_size
+	}
+
+	/// Returns the outlines where blight crosses `threshold` (between 0 and 255),
+	/// as an array of `PoolVector2Array` in world coordinates (x and z). Closed
+	/// outlines repeat their first point at the end, so they can be drawn as-is.
+	#[export]
+	#[profiling::function]
+	pub fn get_blight_contours(&self, _base: &Node, threshold: i32) -> VariantArray {
+		let contours = extract_contours(self.array.data(), threshold.clamp(0, 255) as u8);
+
+		contours
+			.iter()
+			.map(|contour| {
+				let mut points: Vec<Vector2> = contour
+					.points
+					.iter()
+					.map(|point| self.grid2world(*point))
+					.collect();
+				if contour.closed {
+					if let Some(first) = points.first() {
+						points.push(*first);
+					}
+				}
+				Vector2Array::from_vec(points)
+			})
+			.collect::<VariantArray<Unique>>()
+			.into_shared()
+	}
+
+	/// Total length in world units of the outlines where blight crosses `threshold`.
+	/// The longer the frontier, the harder the blight is to contain.
+	#[export]
+	#[profiling::function]
+	pub fn get_frontier_length(&self, _base: &Node, threshold: i32) -> f32 {
+		let contours = extract_contours(self.array.data(), threshold.clamp(0, 255) as u8);
+		let cell_size = self.measurements.plane_size.x / TerrainArray::WIDTH as f32;
+		total_length(&contours) * cell_size
 	}
 
 	/// Returns the average blight value (between 0 and 255) of the circle with
 	/// given `center` and `radius` values.
 	#[profiling::function]
 	pub fn get_average_blight_in_circle(&self, center: Vector3, radius: f32) -> u8 {
+		self.array
+			.query_shape_avg(self.query_circle(center, radius))
+	}
+
+	/// Like [`Self::get_average_blight_in_circle`], but also returns maximum, coverage and
+	/// the damage multiplier of the strains present.
+	#[profiling::function]
+	pub fn get_blight_stats_in_circle(&self, center: Vector3, radius: f32) -> ShapeStats {
+		self.array
+			.query_shape_stats(self.query_circle(center, radius))
+	}
+
+	fn query_circle(&self, center: Vector3, radius: f32) -> Shape {
 		let center_grid = self.world2grid(center);
 		let half_size = TerrainArray::WIDTH as f32 / 2.0;
 		let radius_grid = ((radius / self.measurements.plane_size.x) * half_size) as usize;
-		let circle = Shape::Circle {
+		Shape::Circle {
 			center: center_grid,
 			radius: radius_grid,
+		}
+	}
+
+	/// Blight statistics within `radius` of the segment between `from` and `to`.
+	pub fn get_blight_stats_in_capsule(
+		&self,
+		from: Vector3,
+		to: Vector3,
+		radius: f32,
+	) -> ShapeStats {
+		self.array
+			.query_shape_stats(self.query_capsule(from, to, radius))
+	}
+
+	/// Holds back blight within `thickness` of the segment between `from` and `to`, for the
+	/// barrier with the given id. A `strength` of 255 blocks it entirely, 0 removes the barrier.
+	pub fn set_barrier(
+		&mut self,
+		id: i64,
+		from: Vector3,
+		to: Vector3,
+		thickness: f32,
+		strength: u8,
+	) {
+		let capsule = self.query_capsule(from, to, thickness);
+		self.array.set_barrier(id, capsule, strength);
+	}
+
+	fn query_capsule(&self, from: Vector3, to: Vector3, radius: f32) -> Shape {
+		// Same radius conversion as for circles, so both cover the same area
+		let half_size = TerrainArray::WIDTH as f32 / 2.0;
+		let radius_grid = ((radius / self.measurements.plane_size.x) * half_size) as usize;
+		Shape::Capsule {
+			from: self.world2grid(from),
+			to: self.world2grid(to),
+			radius: radius_grid,
+		}
+	}
+
+	/// Spawns a blight patch of the given strain (e.g. "Withering" or "Creeper").
+	#[export]
+	pub fn spawn_blight(&mut self, _base: &Node, center: Vector3, radius: f32, strain: String) {
+		let strain_id = match Strain::id_by_name(&strain) {
+			Some(id) => id,
+			None => {
+				godot_error!("Invalid blight strain '{}'", strain);
+				return;
+			}
 		};
-		self.array.query_shape_avg(circle)
+
+		self.spawn_strain_circle(center, radius, strain_id);
+	}
+
+	pub fn spawn_strain_circle(&mut self, center: Vector3, radius: f32, strain: StrainId) {
+		let circle = self.query_circle(center, radius);
+		self.array.spawn_strain(circle, strain);
+	}
+
+	/// Number of escalation keyframes reached so far, starting at 0.
+	#[export]
+	pub fn get_escalation_level(&self, _base: &Node) -> i64 {
+		self.array.escalation().level as i64
+	}
+
+	/// Simulation time in seconds since the game started.
+	#[export]
+	pub fn get_elapsed_time(&self, _base: &Node) -> f32 {
+		self.array.elapsed()
+	}
+
+	/// Replaces the escalation schedule; elapsed time is kept. `keyframes` is an array of
+	/// dictionaries with the keys "time", "step_interval", "aggressiveness" and "outbreak_rate",
+	/// sorted by time.
+	#[export]
+	pub fn set_escalation_schedule(&mut self, _base: &Node, keyframes: VariantArray) {
+		match Self::parse_escalation_schedule(&keyframes) {
+			Ok(schedule) => self.array.set_escalation_schedule(schedule),
+			Err(err) => godot_error!("Invalid escalation schedule: {}", err),
+		}
+	}
+
+	fn parse_escalation_schedule(keyframes: &VariantArray) -> Result<EscalationSchedule, String> {
+		let mut parsed = vec![];
+		for keyframe in keyframes.iter() {
+			let dict = keyframe
+				.to::<Dictionary>()
+				.ok_or_else(|| format!("Keyframe {:?} is not a dictionary", keyframe))?;
+			let value = |key: &str| {
+				dict.get(key)
+					.and_then(|value| value.to::<f32>())
+					.ok_or_else(|| format!("Keyframe {:?} has no number '{}'", keyframe, key))
+			};
+
+			parsed.push(EscalationKeyframe {
+				time: value("time")?,
+				step_interval: value("step_interval")?,
+				aggressiveness: value("aggressiveness")?,
+				outbreak_rate: value("outbreak_rate")?,
+			});
+		}
+		EscalationSchedule::new(parsed)
+	}
+
+	pub fn escalation(&self) -> Escalation {
+		self.array.escalation()
 	}
 
 	/// Cleans a circle from blight
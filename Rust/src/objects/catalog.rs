@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::objects::StructureType;
+
+/// Balancing values of a structure type at one upgrade level, see `Godot/Config/structures.ron`.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct StructureStats {
+	/// Ore needed to build it (level 1) or to upgrade to this level, `None` if it can't be built
+	pub build_cost: Option<i32>,
+	pub health: f32,
+	/// Health regained per second while out of blight
+	pub regeneration: f32,
+	/// The radius used when checking if this building is taking damage from blight
+	pub damage_radius: Option<f32>,
+	/// When this building is powered, the radius inside which there is a "protective" effect, cleaning blight
+	pub clean_radius: Option<f32>,
+	/// Ore collected from each ore field within the clean radius, per mining tick
+	pub mining_rate: Option<i32>,
+	/// Resources that can be mined from it
+	pub initial_amount: Option<i32>,
+	/// Maximum amount it holds: water for tanks, resources for fields
+	pub capacity: Option<i32>,
+	/// Amount regained per second, up to the capacity
+	pub amount_regeneration: Option<f32>,
+	pub initially_powered: bool,
+	pub can_be_powered: bool,
+	/// Set for wall segments holding back blight
+	pub barrier: Option<BarrierStats>,
+	/// Radius in which it measures blight, for sensors
+	pub sensor_radius: Option<f32>,
+	/// Water it supplies to its pipe network per second: sources, and tanks (also their intake)
+	pub water_output: Option<f32>,
+	/// Water it needs per second; with less, it cleans a smaller radius
+	pub water_demand: Option<f32>,
+	/// Pipes built from it, `None` if none can be
+	pub pipe: Option<PipeStats>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct BarrierStats {
+	/// Length of the wall segment
+	pub length: f32,
+	/// Distance from the segment within which cells are covered
+	pub thickness: f32,
+	/// How likely blight spreading into covered cells is held back, 255 stops it entirely
+	pub strength: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+pub struct PipeStats {
+	/// Maximum length of a pipe
+	pub max_span: f32,
+	/// Ore per unit of length
+	pub cost_per_unit: f32,
+	/// Fraction of the water lost in transit per unit of length
+	pub loss_per_unit: f32,
+	/// Water it can carry per second
+	pub capacity: f32,
+	pub health: f32,
+	/// Health regained per second while out of blight
+	pub regeneration: f32,
+	/// Distance from the pipe within which blight damages it
+	pub damage_radius: f32,
+}
+
+impl PipeStats {
+	/// Ore needed for a pipe of this length.
+	pub fn cost(&self, length: f32) -> i32 {
+		(self.cost_per_unit * length).ceil() as i32
+	}
+
+	/// Lossless stats for tests, to adjust with struct update syntax.
+	#[cfg(test)]
+	pub fn sample() -> Self {
+		Self {
+			max_span: 10.0,
+			cost_per_unit: 1.0,
+			loss_per_unit: 0.0,
+			capacity: 4.0,
+			health: 60.0,
+			regeneration: 1.0,
+			damage_radius: 0.3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CatalogEntry {
+	pub description: String,
+	/// Stats per upgrade level, the first one being level 1
+	pub levels: Vec<StructureStats>,
+}
+
+/// Stats of every structure type, loaded from a config file. The default one is empty, and only
+/// serves as a placeholder until the file is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct StructureCatalog {
+	entries: HashMap<StructureType, CatalogEntry>,
+}
+
+impl StructureCatalog {
+	pub const PATH: &'static str = "res://Config/structures.ron";
+
+	pub fn from_ron(text: &str) -> Result<Self, String> {
+		let entries: HashMap<StructureType, CatalogEntry> =
+			ron::from_str(text).map_err(|err| err.to_string())?;
+
+		if let Some(missing) = StructureType::ALL
+			.iter()
+			.find(|ty| !entries.contains_key(ty))
+		{
+			return Err(format!("Missing structure type {:?}", missing));
+		}
+		if let Some((ty, _)) = entries.iter().find(|(_, entry)| entry.levels.is_empty()) {
+			return Err(format!("Structure type {:?} has no levels", ty));
+		}
+
+		Ok(Self { entries })
+	}
+
+	/// Stats at level 1.
+	pub fn stats(&self, ty: StructureType) -> StructureStats {
+		self.entry(ty).levels[0]
+	}
+
+	/// Stats at the given level (starting at 1), `None` if there is no such level.
+	pub fn level_stats(&self, ty: StructureType, level: usize) -> Option<StructureStats> {
+		let index = level.checked_sub(1)?;
+		self.entry(ty).levels.get(index).copied()
+	}
+
+	pub fn description(&self, ty: StructureType) -> &str {
+		&self.entry(ty).description
+	}
+
+	fn entry(&self, ty: StructureType) -> &CatalogEntry {
+		self.entries
+			.get(&ty)
+			.expect("Catalog validated to contain all types, and loaded before use")
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pipe_cost_rounds_up() {
+		let stats = PipeStats {
+			cost_per_unit: 1.5,
+			..PipeStats::sample()
+		};
+
+		assert_eq!(stats.cost(3.0), 5);
+		assert_eq!(stats.cost(2.0), 3);
+		assert_eq!(stats.cost(0.0), 0);
+	}
+
+	#[test]
+	fn shipped_catalog_loads() {
+		let catalog =
+			StructureCatalog::from_ron(include_str!("../../../Godot/Config/structures.ron"))
+				.unwrap();
+
+		for ty in StructureType::ALL {
+			assert_eq!(catalog.level_stats(ty, 1), Some(catalog.stats(ty)));
+			assert_eq!(catalog.level_stats(ty, 0), None);
+		}
+	}
+
+	#[test]
+	fn rejects_missing_type() {
+		assert_eq!(
+			StructureCatalog::from_ron("{}").unwrap_err(),
+			"Missing structure type Water"
+		);
+	}
+
+	#[test]
+	fn rejects_type_without_levels() {
+		let entries: Vec<String> = StructureType::ALL
+			.iter()
+			.map(|ty| format!("{:?}: (description: \"\", levels: [])", ty))
+			.collect();
+		let text = format!("{{ {} }}", entries.join(", "));
+
+		let error = StructureCatalog::from_ron(&text).unwrap_err();
+
+		assert!(error.ends_with("has no levels"), "{}", error);
+	}
+
+	#[test]
+	fn rejects_malformed_text() {
+		assert!(StructureCatalog::from_ron("{ Water: (").is_err());
+	}
+}
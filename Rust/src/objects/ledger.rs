@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Currencies the player can earn and spend.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Resource {
+	Ore,
+	/// Water drawn from fields; what isn't used by irrigators is stored in tanks
+	Water,
+	Crystal,
+}
+
+impl Resource {
+	pub const ALL: [Resource; 3] = [Resource::Ore, Resource::Water, Resource::Crystal];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Resource::Ore => "Ore",
+			Resource::Water => "Water",
+			Resource::Crystal => "Crystal",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL
+			.into_iter()
+			.find(|resource| resource.name() == name)
+	}
+}
+
+/// A cost or gain in one or several resources.
+pub type Amounts = [(Resource, i32)];
+
+/// Not enough of a resource to pay a cost.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InsufficientFunds {
+	pub resource: Resource,
+	pub missing: i32,
+}
+
+impl fmt::Display for InsufficientFunds {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} more {} needed", self.missing, self.resource.name())
+	}
+}
+
+/// Balances of all resources, plus the income and expenses in the current and last period.
+#[derive(Debug, Clone, Default)]
+pub struct Ledger {
+	balances: HashMap<Resource, i32>,
+	income: HashMap<Resource, i32>,
+	expense: HashMap<Resource, i32>,
+	last_income: HashMap<Resource, i32>,
+	last_expense: HashMap<Resource, i32>,
+}
+
+impl Ledger {
+	pub fn new(initial: &Amounts) -> Self {
+		Self {
+			balances: initial.iter().copied().collect(),
+			..Default::default()
+		}
+	}
+
+	pub fn balance(&self, resource: Resource) -> i32 {
+		self.balances.get(&resource).copied().unwrap_or(0)
+	}
+
+	/// The first resource there isn't enough of, if any.
+	pub fn check(&self, cost: &Amounts) -> Result<(), InsufficientFunds> {
+		// The same resource may appear several times
+		let mut needed = HashMap::<Resource, i32>::new();
+		for (resource, amount) in cost {
+			*needed.entry(*resource).or_default() += amount;
+		}
+
+		let mut missing: Vec<_> = needed
+			.into_iter()
+			.map(|(resource, amount)| InsufficientFunds {
+				resource,
+				missing: amount - self.balance(resource),
+			})
+			.filter(|funds| funds.missing > 0)
+			.collect();
+		missing.sort_by_key(|funds| Resource::ALL.iter().position(|r| *r == funds.resource));
+
+		match missing.first() {
+			Some(funds) => Err(*funds),
+			None => Ok(()),
+		}
+	}
+
+	pub fn can_afford(&self, cost: &Amounts) -> bool {
+		self.check(cost).is_ok()
+	}
+
+	/// Pays all of the cost, or nothing if any resource is insufficient.
+	pub fn spend(&mut self, cost: &Amounts) -> Result<(), InsufficientFunds> {
+		self.check(cost)?;
+
+		for (resource, amount) in cost {
+			*self.balances.entry(*resource).or_default() -= amount;
+			*self.expense.entry(*resource).or_default() += amount;
+		}
+		Ok(())
+	}
+
+	pub fn earn(&mut self, gain: &Amounts) {
+		for (resource, amount) in gain {
+			*self.balances.entry(*resource).or_default() += amount;
+			*self.income.entry(*resource).or_default() += amount;
+		}
+	}
+
+	/// Removes up to `amount` without failing, for consumption and losses. Returns what was removed.
+	pub fn drain(&mut self, resource: Resource, amount: i32) -> i32 {
+		let balance = self.balances.entry(resource).or_default();
+		let lost = amount.min(*balance).max(0);
+		*balance -= lost;
+		*self.expense.entry(resource).or_default() += lost;
+		lost
+	}
+
+	/// Closes the current accounting period; its income and expenses become the last ones.
+	pub fn end_period(&mut self) {
+		self.last_income = std::mem::take(&mut self.income);
+		self.last_expense = std::mem::take(&mut self.expense);
+	}
+
+	/// Income of the last complete period.
+	pub fn last_income(&self, resource: Resource) -> i32 {
+		self.last_income.get(&resource).copied().unwrap_or(0)
+	}
+
+	/// Expenses of the last complete period.
+	pub fn last_expense(&self, resource: Resource) -> i32 {
+		self.last_expense.get(&resource).copied().unwrap_or(0)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn spend_is_all_or_nothing() {
+		let mut ledger = Ledger::new(&[(Resource::Ore, 10)]);
+
+		let result = ledger.spend(&[(Resource::Ore, 5), (Resource::Water, 1)]);
+
+		assert_eq!(
+			result,
+			Err(InsufficientFunds {
+				resource: Resource::Water,
+				missing: 1
+			})
+		);
+		assert_eq!(ledger.balance(Resource::Ore), 10);
+		ledger.end_period();
+		assert_eq!(ledger.last_expense(Resource::Ore), 0);
+	}
+
+	#[test]
+	fn repeated_resources_add_up() {
+		let mut ledger = Ledger::new(&[(Resource::Ore, 10)]);
+
+		assert_eq!(
+			ledger.check(&[(Resource::Ore, 6), (Resource::Ore, 6)]),
+			Err(InsufficientFunds {
+				resource: Resource::Ore,
+				missing: 2
+			})
+		);
+		assert_eq!(
+			ledger.spend(&[(Resource::Ore, 4), (Resource::Ore, 6)]),
+			Ok(())
+		);
+		assert_eq!(ledger.balance(Resource::Ore), 0);
+	}
+
+	#[test]
+	fn drain_stops_at_zero() {
+		let mut ledger = Ledger::new(&[(Resource::Water, 3)]);
+
+		assert_eq!(ledger.drain(Resource::Water, 5), 3);
+		assert_eq!(ledger.balance(Resource::Water), 0);
+		assert_eq!(ledger.drain(Resource::Crystal, 1), 0);
+	}
+
+	#[test]
+	fn periods_report_income_and_expense() {
+		let mut ledger = Ledger::new(&[(Resource::Ore, 10)]);
+		ledger.earn(&[(Resource::Ore, 7)]);
+		ledger.spend(&[(Resource::Ore, 4)]).unwrap();
+		// Refunds are income
+		ledger.earn(&[(Resource::Ore, 2)]);
+		ledger.end_period();
+
+		assert_eq!(ledger.last_income(Resource::Ore), 9);
+		assert_eq!(ledger.last_expense(Resource::Ore), 4);
+		assert_eq!(ledger.balance(Resource::Ore), 15);
+
+		ledger.end_period();
+		assert_eq!(ledger.last_income(Resource::Ore), 0);
+	}
+}
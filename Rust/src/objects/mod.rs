@@ -1,5 +1,19 @@
+mod catalog;
+mod ledger;
+mod outbreak;
 mod pipe;
+mod pipe_network;
+mod placement;
+mod sensor;
 mod structure;
+mod water_flow;
 
+pub use catalog::*;
+pub use ledger::*;
+pub use outbreak::*;
 pub use pipe::*;
+pub use pipe_network::*;
+pub use placement::*;
+pub use sensor::*;
 pub use structure::*;
+pub use water_flow::*;
@@ -0,0 +1,174 @@
+use gdnative::prelude::*;
+use rand::prelude::*;
+use terrain_array::{Escalation, StrainId, STRAINS};
+
+/// Seconds between the announcement of an outbreak and the blight appearing
+const LEAD_TIME: f32 = 10.0;
+const OUTBREAK_RADIUS: f32 = 6.0;
+/// Random outbreaks are placed inside this square around the origin
+const SPAWN_EXTENT: f32 = 40.0;
+/// How many random positions are tried before giving up on an outbreak
+const PLACEMENT_ATTEMPTS: usize = 20;
+
+/// A blight source that has been announced, and appears once `time_left` runs out.
+#[derive(Debug, Copy, Clone)]
+pub struct Outbreak {
+	pub position: Vector2,
+	pub radius: f32,
+	pub strain: StrainId,
+	pub time_left: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ScriptedOutbreak {
+	/// Seconds since the start of the run at which the blight appears
+	time: f32,
+	/// Random if not set
+	position: Option<Vector2>,
+	radius: f32,
+	strain: StrainId,
+}
+
+/// Decides when and where new blight appears: randomly, at the rate given by the escalation,
+/// and at scripted times.
+#[derive(Debug, Default)]
+pub struct OutbreakScheduler {
+	time: f32,
+	scripted: Vec<ScriptedOutbreak>,
+	pending: Vec<Outbreak>,
+}
+
+impl OutbreakScheduler {
+	/// Schedules the blight of an outbreak to appear `time` seconds after the start of the run.
+	/// It is announced `LEAD_TIME` seconds earlier, or right away if that's already past.
+	///
+	/// Without `position`, a random one is chosen like for random outbreaks. A given position
+	/// must be free like a random one. If there is no free position, the outbreak is skipped with
+	/// a warning.
+	pub fn schedule(
+		&mut self,
+		time: f32,
+		position: Option<Vector2>,
+		radius: f32,
+		strain: StrainId,
+	) {
+		self.scripted.push(ScriptedOutbreak {
+			time,
+			position,
+			radius,
+			strain,
+		});
+	}
+
+	/// Advances time by `dt` seconds. `is_free(position, radius)` decides whether a position is
+	/// far enough from structures and existing blight.
+	///
+	/// Returns the newly announced outbreaks, and the ones whose blight should appear now.
+	pub fn update(
+		&mut self,
+		dt: f32,
+		escalation: &Escalation,
+		is_free: impl Fn(Vector2, f32) -> bool,
+	) -> (Vec<Outbreak>, Vec<Outbreak>) {
+		self.time += dt;
+
+		let mut requested = vec![];
+		let time = self.time;
+		self.scripted.retain(|scripted| {
+			if scripted.time - LEAD_TIME <= time {
+				let lead_time = (scripted.time - time).max(0.0);
+				requested.push((
+					scripted.position,
+					scripted.radius,
+					scripted.strain,
+					lead_time,
+					true,
+				));
+				false
+			} else {
+				true
+			}
+		});
+
+		let mut rng = thread_rng();
+		let probability = (escalation.outbreak_rate / 60.0 * dt).clamp(0.0, 1.0) as f64;
+		if rng.gen_bool(probability) {
+			let strain = rng.gen_range(0..STRAINS.len()) as StrainId;
+			requested.push((None, OUTBREAK_RADIUS, strain, LEAD_TIME, false));
+		}
+
+		let mut announced = vec![];
+		for (position, radius, strain, lead_time, is_scripted) in requested {
+			let position = match position {
+				Some(position) if self.is_free(position, radius, &is_free) => Some(position),
+				Some(position) => {
+					godot_warn!(
+						"Skipped scripted outbreak at {:?}, too close to structures or blight",
+						position
+					);
+					None
+				}
+				None => {
+					let found = self.find_position(radius, &is_free);
+					if found.is_none() && is_scripted {
+						godot_warn!("Skipped scripted outbreak, no free position found");
+					}
+					found
+				}
+			};
+			if let Some(position) = position {
+				let outbreak = Outbreak {
+					position,
+					radius,
+					strain,
+					time_left: lead_time,
+				};
+				self.pending.push(outbreak);
+				announced.push(outbreak);
+			}
+		}
+
+		let mut due = vec![];
+		self.pending.retain_mut(|outbreak| {
+			outbreak.time_left -= dt;
+			if outbreak.time_left <= 0.0 {
+				due.push(*outbreak);
+				false
+			} else {
+				true
+			}
+		});
+
+		(announced, due)
+	}
+
+	fn find_position(
+		&self,
+		radius: f32,
+		is_free: impl Fn(Vector2, f32) -> bool,
+	) -> Option<Vector2> {
+		let mut rng = thread_rng();
+		(0..PLACEMENT_ATTEMPTS)
+			.map(|_| {
+				Vector2::new(
+					rng.gen_range(-SPAWN_EXTENT..SPAWN_EXTENT),
+					rng.gen_range(-SPAWN_EXTENT..SPAWN_EXTENT),
+				)
+			})
+			.find(|position| self.is_free(*position, radius, &is_free))
+	}
+
+	fn is_free(
+		&self,
+		position: Vector2,
+		radius: f32,
+		is_free: impl Fn(Vector2, f32) -> bool,
+	) -> bool {
+		// Pending outbreaks are not visible in the terrain yet
+		let far_from_pending = self
+			.pending
+			.iter()
+			.all(|outbreak| position.distance_to(outbreak.position) > outbreak.radius + radius);
+		far_from_pending && is_free(position, radius)
+	}
+}
@@ -1,12 +1,29 @@
+use gdnative::prelude::*;
+
+use crate::objects::PipeStats;
+
+/// Pipes never lose all their water, however long they are
+const MAX_LOSS: f32 = 0.9;
+
 #[derive(Debug, Copy, Clone)]
 pub struct Pipe {
 	pipe_id: i64,
 	start_id: i64,
 	end_id: i64,
+	/// From the structure it was built from to the new one
+	segment: [Vector2; 2],
+	health: f32,
+	stats: PipeStats,
 }
 
 impl Pipe {
-	pub fn new(pipe_id: i64, start_id: i64, end_id: i64) -> Self {
+	pub fn new(
+		pipe_id: i64,
+		start_id: i64,
+		end_id: i64,
+		segment: [Vector2; 2],
+		stats: PipeStats,
+	) -> Self {
 		assert_ne!(start_id, pipe_id);
 		assert_ne!(start_id, end_id);
 		Self {
@@ -14,9 +31,23 @@ impl Pipe {
 			// canonical order
 			start_id: i64::min(start_id, end_id),
 			end_id: i64::max(end_id, start_id),
+			segment,
+			health: stats.health,
+			stats,
 		}
 	}
 
+	// Setters
+	pub fn deal_damage(&mut self, damage: f32) {
+		self.health -= damage;
+	}
+
+	/// Restores health, up to the maximum.
+	pub fn heal(&mut self, amount: f32) {
+		self.health = (self.health + amount).min(self.max_health());
+	}
+
+	// Getters
 	pub fn pipe_node_id(&self) -> i64 {
 		self.pipe_id
 	}
@@ -28,4 +59,66 @@ impl Pipe {
 	pub fn end_node_id(&self) -> i64 {
 		self.end_id
 	}
+
+	pub fn segment(&self) -> [Vector2; 2] {
+		self.segment
+	}
+
+	pub fn length(&self) -> f32 {
+		let [from, to] = self.segment;
+		from.distance_to(to)
+	}
+
+	pub fn health(&self) -> f32 {
+		self.health
+	}
+
+	pub fn max_health(&self) -> f32 {
+		self.stats.health
+	}
+
+	pub fn regeneration(&self) -> f32 {
+		self.stats.regeneration
+	}
+
+	pub fn damage_radius(&self) -> f32 {
+		self.stats.damage_radius
+	}
+
+	pub fn is_alive(&self) -> bool {
+		self.health > 0.0
+	}
+
+	/// Ore it cost to build.
+	pub fn cost(&self) -> i32 {
+		self.stats.cost(self.length())
+	}
+
+	/// Water it can carry per second.
+	pub fn capacity(&self) -> f32 {
+		self.stats.capacity
+	}
+
+	/// Fraction of the water entering it that is lost on the way.
+	pub fn loss(&self) -> f32 {
+		(self.stats.loss_per_unit * self.length()).clamp(0.0, MAX_LOSS)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn loss_grows_with_length_up_to_max() {
+		let stats = PipeStats {
+			max_span: 100.0,
+			loss_per_unit: 0.05,
+			..PipeStats::sample()
+		};
+		let pipe = |length| Pipe::new(10, 1, 2, [Vector2::ZERO, Vector2::new(length, 0.0)], stats);
+
+		assert!((pipe(10.0).loss() - 0.5).abs() < 1e-6);
+		assert_eq!(pipe(50.0).loss(), MAX_LOSS);
+	}
 }
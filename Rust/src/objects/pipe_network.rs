@@ -0,0 +1,380 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::objects::Pipe;
+
+/// All pipes, with the structures they connect.
+///
+/// Structures are nodes, pipes are edges. Each structure knows its own pipes, so adding or
+/// removing them only touches the structures involved.
+///
+/// Structures whose connections changed are remembered as dirty, so that only their part of the
+/// network needs to be powered again.
+#[derive(Debug, Clone, Default)]
+pub struct PipeNetwork {
+	pipes: HashMap<i64, Pipe>,
+	/// For each structure, its pipes and the structure at their other end
+	adjacency: HashMap<i64, Vec<(i64, i64)>>,
+	powered_pipes: HashSet<i64>,
+	dirty: HashSet<i64>,
+}
+
+impl PipeNetwork {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn insert(&mut self, pipe: Pipe) {
+		let pipe_id = pipe.pipe_node_id();
+		let (start_id, end_id) = (pipe.start_node_id(), pipe.end_node_id());
+
+		if let Some(old) = self.pipes.insert(pipe_id, pipe) {
+			self.unlink(&old);
+		}
+		self.dirty.extend([start_id, end_id]);
+		self.adjacency
+			.entry(start_id)
+			.or_default()
+			.push((pipe_id, end_id));
+		self.adjacency
+			.entry(end_id)
+			.or_default()
+			.push((pipe_id, start_id));
+	}
+
+	pub fn remove_pipe(&mut self, pipe_id: i64) -> Option<Pipe> {
+		let pipe = self.pipes.remove(&pipe_id)?;
+		self.unlink(&pipe);
+		Some(pipe)
+	}
+
+	/// Removes all pipes connected to a structure, and returns them. Structures at their other end
+	/// become dirty.
+	pub fn remove_node(&mut self, node_id: i64) -> Vec<Pipe> {
+		let edges = self.adjacency.remove(&node_id).unwrap_or_default();
+
+		let mut removed = Vec::with_capacity(edges.len());
+		for (pipe_id, _) in edges {
+			if let Some(pipe) = self.pipes.remove(&pipe_id) {
+				self.unlink(&pipe);
+				removed.push(pipe);
+			}
+		}
+		self.dirty.remove(&node_id);
+		removed
+	}
+
+	/// Marks a structure whose ability to supply water changed, e.g. a depleted water field.
+	pub fn mark_dirty(&mut self, node_id: i64) {
+		self.dirty.insert(node_id);
+	}
+
+	/// The connected parts of the network with a dirty structure, each sorted by id.
+	/// Clears the dirty marks.
+	pub fn take_dirty_components(&mut self) -> Vec<Vec<i64>> {
+		let mut dirty: Vec<i64> = std::mem::take(&mut self.dirty).into_iter().collect();
+		dirty.sort_unstable();
+
+		let mut seen = HashSet::new();
+		let mut components = vec![];
+		for node in dirty {
+			if seen.contains(&node) {
+				continue;
+			}
+			let mut component = self.component(node);
+			component.sort_unstable();
+			seen.extend(component.iter().copied());
+			components.push(component);
+		}
+		components
+	}
+
+	pub fn is_pipe_powered(&self, pipe_id: i64) -> bool {
+		self.powered_pipes.contains(&pipe_id)
+	}
+
+	/// Returns whether the powered state of the pipe changed.
+	pub fn set_pipe_powered(&mut self, pipe_id: i64, powered: bool) -> bool {
+		if powered {
+			self.powered_pipes.insert(pipe_id)
+		} else {
+			self.powered_pipes.remove(&pipe_id)
+		}
+	}
+
+	pub fn get(&self, pipe_id: i64) -> Option<&Pipe> {
+		self.pipes.get(&pipe_id)
+	}
+
+	pub fn pipes(&self) -> impl Iterator<Item = &Pipe> {
+		self.pipes.values()
+	}
+
+	/// Pipes can be modified, but not reconnected.
+	pub fn pipes_mut(&mut self) -> impl Iterator<Item = &mut Pipe> {
+		self.pipes.values_mut()
+	}
+
+	pub fn len(&self) -> usize {
+		self.pipes.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.pipes.is_empty()
+	}
+
+	/// Pipes of a structure, as `(pipe_id, other_structure_id)` pairs.
+	pub fn neighbours(&self, node_id: i64) -> &[(i64, i64)] {
+		self.adjacency
+			.get(&node_id)
+			.map(Vec::as_slice)
+			.unwrap_or(&[])
+	}
+
+	/// All structures connected to `node_id` by pipes, including itself.
+	pub fn component(&self, node_id: i64) -> Vec<i64> {
+		let mut visited = HashSet::from([node_id]);
+		let mut queue = VecDeque::from([node_id]);
+		let mut nodes = vec![];
+
+		while let Some(node) = queue.pop_front() {
+			nodes.push(node);
+			for &(_, next) in self.neighbours(node) {
+				if visited.insert(next) {
+					queue.push_back(next);
+				}
+			}
+		}
+		nodes
+	}
+
+	/// Every connected structure mapped to the id of its network: the smallest structure id in it.
+	pub fn component_ids(&self) -> HashMap<i64, i64> {
+		let mut nodes: Vec<i64> = self.adjacency.keys().copied().collect();
+		nodes.sort_unstable();
+
+		let mut result = HashMap::new();
+		for node in nodes {
+			if result.contains_key(&node) {
+				continue;
+			}
+			for member in self.component(node) {
+				result.insert(member, node);
+			}
+		}
+		result
+	}
+
+	/// Inconsistencies between the pipes and the adjacency, described for humans. Structures are
+	/// not known here, so their existence is left to the caller.
+	pub fn validate(&self) -> Vec<String> {
+		let mut violations = vec![];
+
+		for pipe in self.pipes.values() {
+			let pipe_id = pipe.pipe_node_id();
+			for (node_id, other_id) in [
+				(pipe.start_node_id(), pipe.end_node_id()),
+				(pipe.end_node_id(), pipe.start_node_id()),
+			] {
+				if !self.neighbours(node_id).contains(&(pipe_id, other_id)) {
+					violations.push(format!(
+						"pipe {pipe_id} is missing from the adjacency of structure {node_id}"
+					));
+				}
+			}
+		}
+
+		for (node_id, edges) in self.adjacency.iter() {
+			if edges.is_empty() {
+				violations.push(format!("structure {node_id} has an empty adjacency list"));
+			}
+			for (pipe_id, other_id) in edges {
+				let connects = self.pipes.get(pipe_id).map(|pipe| {
+					let ends = [pipe.start_node_id(), pipe.end_node_id()];
+					ends.contains(node_id) && ends.contains(other_id)
+				});
+				if connects != Some(true) {
+					violations.push(format!(
+						"structure {node_id} lists pipe {pipe_id} to {other_id}, which doesn't connect them"
+					));
+				}
+			}
+		}
+
+		for pipe_id in self.powered_pipes.iter() {
+			if !self.pipes.contains_key(pipe_id) {
+				violations.push(format!("removed pipe {pipe_id} is still powered"));
+			}
+		}
+		if !self.dirty.is_empty() {
+			violations.push(format!(
+				"structures {:?} are dirty, but were not powered again",
+				self.dirty
+			));
+		}
+
+		violations
+	}
+
+	/// Removes a pipe from the adjacency of both its ends, which become dirty.
+	fn unlink(&mut self, pipe: &Pipe) {
+		let pipe_id = pipe.pipe_node_id();
+		self.powered_pipes.remove(&pipe_id);
+
+		for node_id in [pipe.start_node_id(), pipe.end_node_id()] {
+			self.dirty.insert(node_id);
+			if let Some(edges) = self.adjacency.get_mut(&node_id) {
+				edges.retain(|(id, _)| *id != pipe_id);
+				if edges.is_empty() {
+					self.adjacency.remove(&node_id);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use gdnative::prelude::*;
+
+	use super::*;
+	use crate::objects::PipeStats;
+
+	fn pipe(pipe_id: i64, from: i64, to: i64) -> Pipe {
+		let segment = [Vector2::ZERO, Vector2::new(1.0, 0.0)];
+		Pipe::new(pipe_id, from, to, segment, PipeStats::sample())
+	}
+
+	/// Chain 1 - 2 - 3 - 4, with pipes 12, 23 and 34.
+	fn chain() -> PipeNetwork {
+		let mut network = PipeNetwork::new();
+		network.insert(pipe(12, 1, 2));
+		network.insert(pipe(23, 2, 3));
+		network.insert(pipe(34, 3, 4));
+		network
+	}
+
+	fn sorted(mut nodes: Vec<i64>) -> Vec<i64> {
+		nodes.sort_unstable();
+		nodes
+	}
+
+	#[test]
+	fn component_follows_pipes() {
+		let mut network = chain();
+		network.insert(pipe(56, 5, 6));
+
+		assert_eq!(sorted(network.component(1)), vec![1, 2, 3, 4]);
+		assert_eq!(sorted(network.component(6)), vec![5, 6]);
+		assert_eq!(network.component(7), vec![7]);
+	}
+
+	#[test]
+	fn removing_pipe_splits_network() {
+		let mut network = chain();
+
+		let removed = network.remove_pipe(23).unwrap();
+
+		assert_eq!(removed.pipe_node_id(), 23);
+		assert_eq!(sorted(network.component(1)), vec![1, 2]);
+		assert_eq!(sorted(network.component(4)), vec![3, 4]);
+		assert!(network.remove_pipe(23).is_none());
+	}
+
+	#[test]
+	fn removing_node_removes_its_pipes() {
+		let mut network = chain();
+
+		let removed = network.remove_node(2);
+
+		let removed_ids = sorted(removed.iter().map(Pipe::pipe_node_id).collect());
+		assert_eq!(removed_ids, vec![12, 23]);
+		assert_eq!(network.len(), 1);
+		assert!(network.neighbours(1).is_empty());
+		assert_eq!(network.neighbours(3), &[(34, 4)]);
+	}
+
+	#[test]
+	fn component_ids_use_smallest_structure() {
+		let mut network = chain();
+		network.insert(pipe(56, 6, 5));
+
+		let ids = network.component_ids();
+
+		assert_eq!(ids[&4], 1);
+		assert_eq!(ids[&5], 5);
+		assert_eq!(ids[&6], 5);
+		assert!(!ids.contains_key(&7));
+	}
+
+	#[test]
+	fn stays_consistent() {
+		let mut network = chain();
+		network.remove_pipe(23);
+		network.remove_node(4);
+		network.insert(pipe(13, 1, 3));
+		network.take_dirty_components();
+
+		assert_eq!(network.validate(), Vec::<String>::new());
+	}
+
+	#[test]
+	fn dirty_components_are_sorted_and_cleared() {
+		let mut network = chain();
+		network.insert(pipe(65, 6, 5));
+
+		assert_eq!(
+			network.take_dirty_components(),
+			vec![vec![1, 2, 3, 4], vec![5, 6]]
+		);
+		assert!(network.take_dirty_components().is_empty());
+
+		network.mark_dirty(6);
+		assert_eq!(network.take_dirty_components(), vec![vec![5, 6]]);
+	}
+
+	#[test]
+	fn split_network_gives_two_dirty_components() {
+		let mut network = chain();
+		network.take_dirty_components();
+
+		network.remove_pipe(23);
+
+		assert_eq!(
+			network.take_dirty_components(),
+			vec![vec![1, 2], vec![3, 4]]
+		);
+	}
+
+	#[test]
+	fn removed_node_is_not_dirty() {
+		let mut network = chain();
+		network.take_dirty_components();
+
+		network.remove_node(4);
+
+		assert_eq!(network.take_dirty_components(), vec![vec![1, 2, 3]]);
+	}
+
+	#[test]
+	fn power_changes_are_reported() {
+		let mut network = chain();
+
+		assert!(network.set_pipe_powered(12, true));
+		assert!(!network.set_pipe_powered(12, true));
+		assert!(network.is_pipe_powered(12));
+		assert!(network.set_pipe_powered(12, false));
+		assert!(!network.set_pipe_powered(12, false));
+		assert!(!network.is_pipe_powered(12));
+	}
+
+	#[test]
+	fn removed_pipe_loses_power() {
+		let mut network = chain();
+		network.set_pipe_powered(23, true);
+
+		network.remove_pipe(23);
+		network.insert(pipe(23, 2, 3));
+
+		assert!(!network.is_pipe_powered(23));
+	}
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+
+use crate::objects::{PipeStats, StructureStats};
+
+/// Maximum distance between a new structure and the one it's built from, unless they are
+/// connected by a pipe (see `PipeStats::max_span`)
+pub const BUILD_RADIUS: f32 = 6.0;
+
+/// Why a structure can't be placed somewhere, or two structures can't be connected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlacementError {
+	/// The structure type name doesn't exist
+	UnknownType,
+	/// The structure it's built from doesn't exist or can't be built from
+	InvalidSource,
+	/// The structure type can't be built by the player
+	NotBuildable,
+	/// Too far away from the structure it's built from
+	OutOfRange,
+	/// Too close to another structure
+	Overlapping,
+	/// Built in blight dense enough to damage it
+	BlightedGround,
+	/// The structure to connect to doesn't exist or can't take a pipe
+	InvalidTarget,
+	/// The structures are already connected by a pipe
+	AlreadyConnected,
+	InsufficientOre {
+		cost: i32,
+	},
+}
+
+impl PlacementError {
+	/// Stable identifier, for scripts.
+	pub fn name(&self) -> &'static str {
+		match self {
+			PlacementError::UnknownType => "UnknownType",
+			PlacementError::InvalidSource => "InvalidSource",
+			PlacementError::NotBuildable => "NotBuildable",
+			PlacementError::OutOfRange => "OutOfRange",
+			PlacementError::Overlapping => "Overlapping",
+			PlacementError::BlightedGround => "BlightedGround",
+			PlacementError::InvalidTarget => "InvalidTarget",
+			PlacementError::AlreadyConnected => "AlreadyConnected",
+			PlacementError::InsufficientOre { .. } => "InsufficientOre",
+		}
+	}
+}
+
+/// Maximum distance between a new structure and the one it's built from, connected by `pipe`
+/// if it gets one.
+pub fn build_radius(pipe: Option<PipeStats>) -> f32 {
+	pipe.map_or(BUILD_RADIUS, |pipe| pipe.max_span)
+}
+
+/// The placement rules that only depend on the catalog: whether a structure with `stats` can be
+/// built `distance` away from the one it's built from. Returns the cost, pipe included.
+pub fn build_cost(
+	stats: &StructureStats,
+	pipe: Option<PipeStats>,
+	distance: f32,
+) -> Result<i32, PlacementError> {
+	let cost = stats.build_cost.ok_or(PlacementError::NotBuildable)?;
+	if distance > build_radius(pipe) {
+		return Err(PlacementError::OutOfRange);
+	}
+
+	// Longer pipes cost more
+	Ok(cost + pipe.map_or(0, |pipe| pipe.cost(distance)))
+}
+
+impl fmt::Display for PlacementError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			PlacementError::UnknownType => write!(f, "Unknown structure type"),
+			PlacementError::InvalidSource => write!(f, "Can't build from here"),
+			PlacementError::NotBuildable => write!(f, "Can't be built"),
+			PlacementError::OutOfRange => write!(f, "Out of range"),
+			PlacementError::Overlapping => write!(f, "Too close to another structure"),
+			PlacementError::BlightedGround => write!(f, "Ground is blighted"),
+			PlacementError::InvalidTarget => write!(f, "Can't connect to this"),
+			PlacementError::AlreadyConnected => write!(f, "Already connected"),
+			PlacementError::InsufficientOre { cost } => write!(f, "Not enough ore (cost {cost})"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn stats(build_cost: Option<i32>) -> StructureStats {
+		StructureStats {
+			build_cost,
+			health: 100.0,
+			regeneration: 0.0,
+			damage_radius: None,
+			clean_radius: None,
+			mining_rate: None,
+			initial_amount: None,
+			capacity: None,
+			amount_regeneration: None,
+			initially_powered: false,
+			can_be_powered: true,
+			barrier: None,
+			sensor_radius: None,
+			water_output: None,
+			water_demand: None,
+			pipe: None,
+		}
+	}
+
+	fn pipe(max_span: f32, cost_per_unit: f32) -> PipeStats {
+		PipeStats {
+			max_span,
+			cost_per_unit,
+			..PipeStats::sample()
+		}
+	}
+
+	#[test]
+	fn fields_cant_be_built() {
+		assert_eq!(
+			build_cost(&stats(None), None, 1.0),
+			Err(PlacementError::NotBuildable)
+		);
+	}
+
+	#[test]
+	fn range_depends_on_pipe() {
+		let far = BUILD_RADIUS + 1.0;
+		assert_eq!(
+			build_cost(&stats(Some(10)), None, far),
+			Err(PlacementError::OutOfRange)
+		);
+		assert_eq!(
+			build_cost(&stats(Some(10)), Some(pipe(far - 0.5, 0.0)), far),
+			Err(PlacementError::OutOfRange)
+		);
+		assert_eq!(
+			build_cost(&stats(Some(10)), Some(pipe(far, 0.0)), far),
+			Ok(10)
+		);
+	}
+
+	#[test]
+	fn pipe_length_adds_to_cost() {
+		assert_eq!(build_cost(&stats(Some(10)), None, 4.0), Ok(10));
+		assert_eq!(
+			build_cost(&stats(Some(10)), Some(pipe(8.0, 1.5)), 4.0),
+			Ok(16)
+		);
+	}
+}
@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use terrain_array::ShapeStats;
+
+/// Number of readings kept per sensor
+const HISTORY_LEN: usize = 30;
+
+/// Blight measured by a sensor at one point in time.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SensorReading {
+	pub average: u8,
+	pub max: u8,
+	/// Fraction of the sensor's radius with any blight
+	pub blighted_fraction: f32,
+}
+
+impl From<ShapeStats> for SensorReading {
+	fn from(stats: ShapeStats) -> Self {
+		Self {
+			average: stats.average,
+			max: stats.max,
+			blighted_fraction: stats.blighted_fraction,
+		}
+	}
+}
+
+/// Readings of one sensor, and whether it is currently alerting.
+#[derive(Debug, Clone, Default)]
+pub struct SensorLog {
+	readings: VecDeque<SensorReading>,
+	alerting: bool,
+}
+
+impl SensorLog {
+	/// Adds a reading, dropping the oldest ones. Returns `Some(alerting)` when the maximum
+	/// blight crossed `threshold`, in either direction.
+	pub fn record(&mut self, reading: SensorReading, threshold: u8) -> Option<bool> {
+		if self.readings.len() == HISTORY_LEN {
+			self.readings.pop_front();
+		}
+		self.readings.push_back(reading);
+
+		let alerting = reading.max > threshold;
+		if alerting != self.alerting {
+			self.alerting = alerting;
+			Some(alerting)
+		} else {
+			None
+		}
+	}
+
+	/// Oldest reading first
+	pub fn readings(&self) -> impl Iterator<Item = &SensorReading> {
+		self.readings.iter()
+	}
+
+	pub fn latest(&self) -> Option<SensorReading> {
+		self.readings.back().copied()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn reading(max: u8) -> SensorReading {
+		SensorReading {
+			average: max / 2,
+			max,
+			blighted_fraction: 0.5,
+		}
+	}
+
+	#[test]
+	fn alerts_only_when_crossing_threshold() {
+		let mut log = SensorLog::default();
+
+		assert_eq!(log.record(reading(50), 100), None);
+		assert_eq!(log.record(reading(150), 100), Some(true));
+		assert_eq!(log.record(reading(200), 100), None);
+		assert_eq!(log.record(reading(100), 100), Some(false));
+		assert_eq!(log.record(reading(0), 100), None);
+	}
+
+	#[test]
+	fn keeps_latest_readings() {
+		let mut log = SensorLog::default();
+		for max in 0..HISTORY_LEN as u8 + 5 {
+			log.record(reading(max), u8::MAX);
+		}
+
+		assert_eq!(log.readings().count(), HISTORY_LEN);
+		assert_eq!(log.readings().next().unwrap().max, 5);
+		assert_eq!(log.latest().unwrap().max, HISTORY_LEN as u8 + 4);
+	}
+}
@@ -1,28 +1,84 @@
 use gdnative::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
 
+use crate::objects::{BarrierStats, PipeStats, StructureStats};
 use crate::Vector2Ext;
 use rstar::{RTreeObject, AABB};
+use serde::Deserialize;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
 pub enum StructureType {
 	Water,
 	Ore,
 	Pump,
 	Irrigation,
+	Barrier,
+	Tank,
+	Sensor,
 }
 
 impl StructureType {
-	pub fn from_name(ty_name: &str) -> Self {
-		match ty_name {
-			"Water" => StructureType::Water,
-			"Ore" => StructureType::Ore,
-			"Pump" => StructureType::Pump,
-			"Irrigation" => StructureType::Irrigation,
-			_ => unreachable!(&format!("Invalid name '{}'", ty_name)),
+	pub const ALL: [StructureType; 7] = [
+		StructureType::Water,
+		StructureType::Ore,
+		StructureType::Pump,
+		StructureType::Irrigation,
+		StructureType::Barrier,
+		StructureType::Tank,
+		StructureType::Sensor,
+	];
+
+	/// Name used in scenes, scripts and the catalog.
+	pub fn name(&self) -> &'static str {
+		match self {
+			StructureType::Water => "Water",
+			StructureType::Ore => "Ore",
+			StructureType::Pump => "Pump",
+			StructureType::Irrigation => "Irrigation",
+			StructureType::Barrier => "Barrier",
+			StructureType::Tank => "Tank",
+			StructureType::Sensor => "Sensor",
 		}
 	}
 }
 
+impl FromStr for StructureType {
+	type Err = ParseStructureTypeError;
+
+	fn from_str(ty_name: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|ty| ty.name() == ty_name)
+			.ok_or_else(|| ParseStructureTypeError {
+				name: ty_name.to_string(),
+			})
+	}
+}
+
+impl TryFrom<&str> for StructureType {
+	type Error = ParseStructureTypeError;
+
+	fn try_from(ty_name: &str) -> Result<Self, Self::Error> {
+		ty_name.parse()
+	}
+}
+
+/// A string that doesn't name any `StructureType`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseStructureTypeError {
+	pub name: String,
+}
+
+impl fmt::Display for ParseStructureTypeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Invalid structure type '{}'", self.name)
+	}
+}
+
+impl Error for ParseStructureTypeError {}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Structure {
 	ty: StructureType,
@@ -31,56 +87,63 @@ pub struct Structure {
 	health: f32,
 	powered: bool,
 	amount: Option<i32>,
+	/// Upgrade level, starting at 1
+	level: usize,
+	/// Ore spent on building and upgrading
+	value: Option<i32>,
+	/// End points of wall-like structures
+	segment: Option<[Vector2; 2]>,
+	/// Fraction of a unit regained, but not yet added to `amount`
+	amount_progress: f32,
+	/// Fraction of its water demand it gets, from 0 to 1
+	water_supply: f32,
+	stats: StructureStats,
 }
 
 impl Structure {
-	pub fn new(ty: StructureType, position: Vector2, id: i64, health: f32) -> Structure {
+	pub fn new(ty: StructureType, position: Vector2, id: i64, stats: StructureStats) -> Structure {
 		Self {
 			ty,
 			position,
 			id,
-			health,
-			powered: Self::initially_powered(ty),
-			amount: Self::initial_amount(ty),
+			health: stats.health,
+			powered: stats.initially_powered,
+			amount: stats.initial_amount,
+			level: 1,
+			value: stats.build_cost,
+			segment: None,
+			amount_progress: 0.0,
+			water_supply: 0.0,
+			stats,
 		}
 	}
 
-	pub fn ty_name(&self) -> &'static str {
-		match self.ty {
-			StructureType::Water => "Water",
-			StructureType::Ore => "Ore",
-			StructureType::Pump => "Pump",
-			StructureType::Irrigation => "Irrigation",
-		}
+	pub fn with_segment(mut self, segment: [Vector2; 2]) -> Self {
+		self.segment = Some(segment);
+		self
 	}
 
-	pub fn ty_description(&self) -> &'static str {
-		match self.ty {
-			StructureType::Water => "A water field can be connected to pumps and irrigation plants.",
-			StructureType::Ore => "Ore is your money; building pumps and irrigation plants costs ore.",
-			StructureType::Pump => "A water pump is a relay, allowing to extend the distance to your irrigation plants.",
-			StructureType::Irrigation => "Irrigation plants keep the blight away and mine ore.",
-		}
+	pub fn ty_name(&self) -> &'static str {
+		self.ty.name()
 	}
 
 	// The radius used when checking if this building is taking damage from blight
 	pub fn damage_radius(&self) -> Option<f32> {
-		match self.ty {
-			StructureType::Water => None, // Doesn't take damage
-			StructureType::Ore => None,   // Doesn't take damage
-			StructureType::Pump => Some(1.0),
-			StructureType::Irrigation => Some(1.5),
-		}
+		self.stats.damage_radius
 	}
 
 	// When this building is powered, the radius inside which there is a "protective" effect, cleaning blight
 	pub fn clean_radius(&self) -> Option<f32> {
-		match self.ty {
-			StructureType::Water => Some(1.5),
-			StructureType::Ore => None, // Doesn't clean
-			StructureType::Pump => None,
-			StructureType::Irrigation => Some(5.0),
-		}
+		self.stats.clean_radius
+	}
+
+	/// The clean radius, shrunk when it gets only part of the water it needs.
+	pub fn effective_clean_radius(&self) -> Option<f32> {
+		let supply = match self.stats.water_demand {
+			Some(_) => self.water_supply,
+			None => 1.0,
+		};
+		self.clean_radius().map(|radius| radius * supply)
 	}
 
 	// Setters
@@ -89,11 +152,32 @@ impl Structure {
 		self.health -= damage;
 	}
 
+	/// Switches to the stats of the next level. Damage taken so far is kept.
+	pub fn upgrade(&mut self, next_stats: StructureStats) {
+		let damage = self.max_health() - self.health;
+		self.health = next_stats.health - damage;
+		self.value = self
+			.value
+			.map(|value| value + next_stats.build_cost.unwrap_or(0));
+		self.level += 1;
+		self.stats = next_stats;
+	}
+
+	/// Restores health, up to the maximum.
+	pub fn heal(&mut self, amount: f32) {
+		self.health = (self.health + amount).min(self.max_health());
+	}
+
 	pub fn set_powered(&mut self, powered: bool) {
 		assert!(self.can_be_powered());
 		self.powered = powered;
 	}
 
+	pub fn set_water_supply(&mut self, supply: f32) {
+		assert!(self.water_demand().is_some());
+		self.water_supply = supply.clamp(0.0, 1.0);
+	}
+
 	/// Mines the amount, panics if non-mineable structure.
 	/// Returns the truly mined amount (if depleted)
 	#[must_use]
@@ -108,6 +192,32 @@ impl Structure {
 		}
 	}
 
+	/// Stores the amount, up to the capacity. Panics if the structure can't store anything.
+	/// Returns the truly stored amount (if full)
+	#[must_use]
+	pub fn store_amount(&mut self, amount: i32) -> i32 {
+		let capacity = self.capacity().expect("non-storing structure");
+		let stored = self.amount.as_mut().expect("non-storing structure");
+
+		let added = amount.min(capacity - *stored).max(0);
+		*stored += added;
+		added
+	}
+
+	/// Regains amount over `dt` seconds, up to the capacity. Returns the truly regained amount.
+	#[must_use]
+	pub fn regenerate_amount(&mut self, dt: f32) -> i32 {
+		let rate = match self.stats.amount_regeneration {
+			Some(rate) => rate,
+			None => return 0,
+		};
+
+		self.amount_progress += rate * dt;
+		let whole = self.amount_progress.floor();
+		self.amount_progress -= whole;
+		self.store_amount(whole as i32)
+	}
+
 	// Getters
 	pub fn ty(&self) -> StructureType {
 		self.ty
@@ -121,6 +231,62 @@ impl Structure {
 		self.position
 	}
 
+	pub fn health(&self) -> f32 {
+		self.health
+	}
+
+	pub fn max_health(&self) -> f32 {
+		self.stats.health
+	}
+
+	pub fn regeneration(&self) -> f32 {
+		self.stats.regeneration
+	}
+
+	/// Ore needed to restore full health: repairing a wreck costs half of its value.
+	/// `None` if the structure can't be built (and thus not repaired).
+	pub fn repair_cost(&self) -> Option<i32> {
+		let missing = (self.max_health() - self.health).max(0.0) / self.max_health();
+		self.value
+			.map(|value| (missing * value as f32 / 2.0).ceil() as i32)
+	}
+
+	pub fn sensor_radius(&self) -> Option<f32> {
+		self.stats.sensor_radius
+	}
+
+	/// Ore returned when demolishing: half of its value, less for damaged structures.
+	/// `None` if the structure can't be built (and thus not demolished).
+	pub fn demolish_refund(&self) -> Option<i32> {
+		let intact = (self.health / self.max_health()).clamp(0.0, 1.0);
+		self.value
+			.map(|value| (intact * value as f32 / 2.0).floor() as i32)
+	}
+
+	pub fn amount_regeneration(&self) -> Option<f32> {
+		self.stats.amount_regeneration
+	}
+
+	pub fn water_output(&self) -> Option<f32> {
+		self.stats.water_output
+	}
+
+	pub fn water_demand(&self) -> Option<f32> {
+		self.stats.water_demand
+	}
+
+	pub fn water_supply(&self) -> f32 {
+		self.water_supply
+	}
+
+	pub fn capacity(&self) -> Option<i32> {
+		self.stats.capacity
+	}
+
+	pub fn mining_rate(&self) -> Option<i32> {
+		self.stats.mining_rate
+	}
+
 	pub fn is_alive(&self) -> bool {
 		self.health > 0.0
 	}
@@ -129,46 +295,45 @@ impl Structure {
 		self.powered
 	}
 
-	// FIXME duplicated in World.gd
-	pub fn build_cost(&self) -> Option<i32> {
-		match self.ty {
-			StructureType::Water => None, // not toggleable
-			StructureType::Ore => None,
-			StructureType::Pump => Some(15),
-			StructureType::Irrigation => Some(50),
-		}
+	pub fn segment(&self) -> Option<[Vector2; 2]> {
+		self.segment
+	}
+
+	pub fn barrier(&self) -> Option<BarrierStats> {
+		self.stats.barrier
+	}
+
+	/// Stats of pipes built from it.
+	pub fn pipe(&self) -> Option<PipeStats> {
+		self.stats.pipe
+	}
+
+	pub fn level(&self) -> usize {
+		self.level
+	}
+
+	/// Ore spent on building and upgrading, `None` if it can't be built.
+	pub fn value(&self) -> Option<i32> {
+		self.value
 	}
 
 	pub fn can_be_powered(&self) -> bool {
-		match self.ty {
-			StructureType::Water => false, // not toggleable
-			StructureType::Ore => false,
-			StructureType::Pump => true,
-			StructureType::Irrigation => true,
-		}
+		self.stats.can_be_powered
 	}
 
 	pub fn amount(&self) -> i32 {
 		self.amount.expect("Queried amount of invalid type")
 	}
 
-	// Constructor helpers
-	fn initially_powered(ty: StructureType) -> bool {
-		match ty {
-			StructureType::Water => true,
-			StructureType::Ore => false,
-			StructureType::Pump => false,
-			StructureType::Irrigation => false,
-		}
-	}
-
-	fn initial_amount(ty: StructureType) -> Option<i32> {
-		match ty {
-			StructureType::Water => Some(50),
-			StructureType::Ore => Some(50),
-			StructureType::Pump => None,
-			StructureType::Irrigation => None,
-		}
+	/// Whether both have the same type, position, health, amount, level and power state.
+	/// `==` only compares positions, for the RTree.
+	pub fn same_state(&self, other: &Structure) -> bool {
+		self.ty == other.ty
+			&& self.position == other.position
+			&& self.health == other.health
+			&& self.amount == other.amount
+			&& self.level == other.level
+			&& self.powered == other.powered
 	}
 }
 
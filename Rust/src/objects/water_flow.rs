@@ -0,0 +1,435 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::objects::{Pipe, PipeNetwork};
+
+/// How a structure takes part in the water flow of its network, in water per second.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FlowSpec {
+	pub can_be_powered: bool,
+	/// Water it supplies to its network
+	pub output: f32,
+	/// Water it supplies only when the outputs of its network don't cover the demand
+	pub reserve: f32,
+	pub demand: f32,
+	/// Water it can store from the outputs the network doesn't need
+	pub intake: f32,
+}
+
+/// Water moving through one connected part of the pipe network.
+///
+/// All sources of a network share its demand, in proportion to their output. Pipes carry at most
+/// their capacity, so the structures behind a saturated pipe only get a share of what they need,
+/// and lose part of the water on the way. Tanks only fill through the capacity consumers leave.
+#[derive(Debug, Clone, Default)]
+pub struct WaterFlow {
+	/// Structures reached by water, sources included
+	pub powered: HashSet<i64>,
+	pub pipe_ids: HashSet<i64>,
+	/// Fraction of its demand each reached consumer gets, from 0 to 1
+	pub supply: HashMap<i64, f32>,
+	/// Water drawn from each source (negative) or stored in it (positive), per second
+	pub rates: HashMap<i64, f32>,
+	/// Water going through each pipe, per second
+	pub pipe_loads: HashMap<i64, f32>,
+}
+
+impl WaterFlow {
+	/// Computes the flow in `component`, which must be all the structures of one connected part
+	/// of `network`.
+	pub fn compute(
+		network: &PipeNetwork,
+		component: &[i64],
+		spec: impl Fn(i64) -> FlowSpec,
+	) -> Self {
+		let specs: HashMap<i64, FlowSpec> = component.iter().map(|id| (*id, spec(*id))).collect();
+
+		let mut sources: Vec<i64> = component
+			.iter()
+			.copied()
+			.filter(|id| specs[id].output > 0.0 || specs[id].reserve > 0.0)
+			.collect();
+		sources.sort_unstable();
+
+		let mut result = Self::default();
+		if sources.is_empty() {
+			return result;
+		}
+
+		// Each structure gets its water through the pipe it's first reached by. Breadth-first, so
+		// long chains of pumps don't grow the stack.
+		let mut parents = HashMap::<i64, (i64, i64)>::new();
+		let mut order = vec![];
+		let mut visited: HashSet<i64> = sources.iter().copied().collect();
+		let mut queue: VecDeque<i64> = sources.iter().copied().collect();
+
+		while let Some(node) = queue.pop_front() {
+			if specs[&node].can_be_powered {
+				result.powered.insert(node);
+			}
+
+			for &(pipe_id, next) in network.neighbours(node) {
+				if !parents.contains_key(&node) {
+					result.pipe_ids.insert(pipe_id);
+				}
+				if !visited.insert(next) {
+					continue;
+				}
+				if specs[&next].can_be_powered {
+					result.pipe_ids.insert(pipe_id);
+				}
+				parents.insert(next, (pipe_id, node));
+				order.push(next);
+				queue.push_back(next);
+			}
+		}
+
+		let losses: HashMap<i64, f32> = parents
+			.values()
+			.map(|(pipe_id, _)| (*pipe_id, network.get(*pipe_id).map_or(0.0, Pipe::loss)))
+			.collect();
+		let capacities: HashMap<i64, f32> = parents
+			.values()
+			.map(|(pipe_id, _)| {
+				let capacity = network.get(*pipe_id).map_or(0.0, Pipe::capacity);
+				(*pipe_id, capacity)
+			})
+			.collect();
+
+		// Demand flows back towards the sources, adding up in the pipes on the way. Pipes need
+		// more water going in than coming out, to make up for what they lose.
+		let loads_of = |amounts: &HashMap<i64, f32>| {
+			let mut totals = amounts.clone();
+			let mut pipe_loads = HashMap::new();
+			for node in order.iter().rev() {
+				let (pipe_id, parent) = parents[node];
+				let load = totals.get(node).copied().unwrap_or(0.0) / (1.0 - losses[&pipe_id]);
+				*totals.entry(parent).or_default() += load;
+				pipe_loads.insert(pipe_id, load);
+			}
+			pipe_loads
+		};
+		// Fraction of an amount that can reach each structure, through pipes saturated by the
+		// given loads
+		let reach_of = |pipe_loads: &HashMap<i64, f32>, capacity: &dyn Fn(i64) -> f32| {
+			let mut reach: HashMap<i64, f32> = sources.iter().map(|id| (*id, 1.0)).collect();
+			for node in order.iter() {
+				let (pipe_id, parent) = parents[node];
+				let load = pipe_loads[&pipe_id];
+				let capacity = capacity(pipe_id);
+				let limit = if load > capacity {
+					capacity / load
+				} else {
+					1.0
+				};
+				reach.insert(*node, reach[&parent] * limit);
+			}
+			reach
+		};
+
+		let demands = specs.iter().map(|(id, spec)| (*id, spec.demand)).collect();
+		result.pipe_loads = loads_of(&demands);
+		let reach = reach_of(&result.pipe_loads, &|pipe_id| capacities[&pipe_id]);
+
+		// Water sources need to send for each unit arriving at a structure
+		let mut carried: HashMap<i64, f32> = sources.iter().map(|id| (*id, 1.0)).collect();
+		for node in order.iter() {
+			let (pipe_id, parent) = parents[node];
+			carried.insert(*node, carried[&parent] / (1.0 - losses[&pipe_id]));
+		}
+
+		let wanted: f32 = specs
+			.iter()
+			.filter_map(|(id, spec)| reach.get(id).map(|reach| spec.demand * reach * carried[id]))
+			.sum();
+		let output: f32 = sources.iter().map(|id| specs[id].output).sum();
+		let reserve: f32 = sources.iter().map(|id| specs[id].reserve).sum();
+
+		let from_output = output.min(wanted);
+		let from_reserve = reserve.min(wanted - from_output);
+		let share = if wanted > 0.0 {
+			(from_output + from_reserve) / wanted
+		} else {
+			0.0
+		};
+
+		for id in specs.keys().filter(|id| specs[id].demand > 0.0) {
+			if let Some(reach) = reach.get(id) {
+				result.supply.insert(*id, reach * share);
+			}
+		}
+		for (pipe_id, load) in result.pipe_loads.iter_mut() {
+			*load = load.min(capacities[pipe_id]) * share;
+		}
+
+		// What the network doesn't need fills its tanks, through what's left of the pipes
+		let intakes = specs.iter().map(|(id, spec)| (*id, spec.intake)).collect();
+		let intake_reach = reach_of(&loads_of(&intakes), &|pipe_id| {
+			(capacities[&pipe_id] - result.pipe_loads[&pipe_id]).max(0.0)
+		});
+		let intakes: HashMap<i64, f32> = intake_reach
+			.iter()
+			.filter(|(id, _)| specs[id].intake > 0.0)
+			.map(|(id, reach)| (*id, specs[id].intake * reach))
+			.collect();
+		let intake: f32 = intakes
+			.iter()
+			.map(|(id, intake)| intake * carried[id])
+			.sum();
+		let stored = (output - from_output).min(intake);
+		if stored > 0.0 {
+			let arriving: HashMap<i64, f32> = intakes
+				.iter()
+				.map(|(id, tank_intake)| (*id, tank_intake * stored / intake))
+				.collect();
+			for (pipe_id, load) in loads_of(&arriving) {
+				*result.pipe_loads.entry(pipe_id).or_default() += load;
+			}
+			for (id, rate) in arriving {
+				*result.rates.entry(id).or_default() += rate;
+			}
+		}
+
+		for id in sources.iter() {
+			let spec = specs[id];
+			let mut drawn = 0.0;
+			if spec.output > 0.0 {
+				drawn += spec.output * (from_output + stored) / output;
+			}
+			if spec.reserve > 0.0 {
+				drawn += spec.reserve * from_reserve / reserve;
+			}
+			if drawn > 0.0 {
+				*result.rates.entry(*id).or_default() -= drawn;
+			}
+		}
+
+		result
+	}
+}
+
+/// Water moved by a structure at a steady rate, counted in whole units.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct WaterMeter {
+	/// Water stored (positive) or drawn (negative) per second
+	pub rate: f32,
+	/// Part of a unit moved, but not yet counted
+	progress: f32,
+}
+
+impl WaterMeter {
+	/// Whole units moved over `dt` seconds; the rest is kept for later.
+	pub fn advance(&mut self, dt: f32) -> i32 {
+		self.progress += self.rate * dt;
+		let whole = self.progress.trunc();
+		self.progress -= whole;
+		whole as i32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use gdnative::prelude::*;
+
+	use super::*;
+	use crate::objects::PipeStats;
+
+	const FIELD: i64 = 1;
+	const IRRIGATOR: i64 = 2;
+	const TANK: i64 = 3;
+	const SECOND_FIELD: i64 = 4;
+
+	fn pipe_stats(loss_per_unit: f32, capacity: f32) -> PipeStats {
+		PipeStats {
+			loss_per_unit,
+			capacity,
+			..PipeStats::sample()
+		}
+	}
+
+	/// Network of pipes `(pipe_id, from, to, length)` with the given stats.
+	fn network(pipes: &[(i64, i64, i64, f32)], stats: PipeStats) -> PipeNetwork {
+		let mut network = PipeNetwork::new();
+		for &(pipe_id, from, to, length) in pipes {
+			let segment = [Vector2::ZERO, Vector2::new(length, 0.0)];
+			network.insert(Pipe::new(pipe_id, from, to, segment, stats));
+		}
+		network
+	}
+
+	fn field(output: f32) -> FlowSpec {
+		FlowSpec {
+			output,
+			..Default::default()
+		}
+	}
+
+	fn irrigator(demand: f32) -> FlowSpec {
+		FlowSpec {
+			can_be_powered: true,
+			demand,
+			..Default::default()
+		}
+	}
+
+	fn tank(output: f32, has_water: bool, is_full: bool) -> FlowSpec {
+		FlowSpec {
+			can_be_powered: true,
+			reserve: if has_water { output } else { 0.0 },
+			intake: if is_full { 0.0 } else { output },
+			..Default::default()
+		}
+	}
+
+	fn assert_near(actual: f32, expected: f32) {
+		assert!(
+			(actual - expected).abs() < 1e-4,
+			"expected {expected}, got {actual}"
+		);
+	}
+
+	#[test]
+	fn tank_stores_what_irrigators_dont_need() {
+		let network = network(
+			&[(10, FIELD, IRRIGATOR, 1.0), (11, FIELD, TANK, 1.0)],
+			pipe_stats(0.0, 10.0),
+		);
+		let specs = HashMap::from([
+			(FIELD, field(3.0)),
+			(IRRIGATOR, irrigator(1.0)),
+			(TANK, tank(2.0, true, false)),
+		]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, IRRIGATOR, TANK], |id| specs[&id]);
+
+		assert_near(flow.supply[&IRRIGATOR], 1.0);
+		assert_near(flow.rates[&FIELD], -3.0);
+		// Not drained while the field covers the demand
+		assert_near(flow.rates[&TANK], 2.0);
+	}
+
+	#[test]
+	fn tank_supplies_dry_network() {
+		let network = network(
+			&[(10, FIELD, IRRIGATOR, 1.0), (11, IRRIGATOR, TANK, 1.0)],
+			pipe_stats(0.0, 10.0),
+		);
+		let specs = HashMap::from([
+			(FIELD, field(0.0)),
+			(IRRIGATOR, irrigator(1.0)),
+			(TANK, tank(2.0, true, false)),
+		]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, IRRIGATOR, TANK], |id| specs[&id]);
+
+		assert_near(flow.supply[&IRRIGATOR], 1.0);
+		assert_near(flow.rates[&TANK], -1.0);
+		assert!(!flow.rates.contains_key(&FIELD));
+		assert!(flow.powered.contains(&TANK));
+	}
+
+	#[test]
+	fn full_tank_stores_nothing() {
+		let network = network(&[(10, FIELD, TANK, 1.0)], pipe_stats(0.0, 10.0));
+		let specs = HashMap::from([(FIELD, field(3.0)), (TANK, tank(2.0, true, true))]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, TANK], |id| specs[&id]);
+
+		assert!(flow.rates.is_empty());
+	}
+
+	#[test]
+	fn empty_tank_without_field_powers_nothing() {
+		let network = network(&[(10, IRRIGATOR, TANK, 1.0)], pipe_stats(0.0, 10.0));
+		let specs = HashMap::from([(IRRIGATOR, irrigator(1.0)), (TANK, tank(2.0, false, false))]);
+
+		let flow = WaterFlow::compute(&network, &[IRRIGATOR, TANK], |id| specs[&id]);
+
+		assert!(flow.powered.is_empty());
+		assert!(flow.supply.is_empty());
+		assert!(flow.rates.is_empty());
+	}
+
+	#[test]
+	fn sources_share_demand_by_output() {
+		let network = network(
+			&[
+				(10, FIELD, IRRIGATOR, 1.0),
+				(11, SECOND_FIELD, IRRIGATOR, 1.0),
+			],
+			pipe_stats(0.0, 10.0),
+		);
+		let specs = HashMap::from([
+			(FIELD, field(3.0)),
+			(SECOND_FIELD, field(1.0)),
+			(IRRIGATOR, irrigator(2.0)),
+		]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, IRRIGATOR, SECOND_FIELD], |id| specs[&id]);
+
+		assert_near(flow.supply[&IRRIGATOR], 1.0);
+		assert_near(flow.rates[&FIELD], -1.5);
+		assert_near(flow.rates[&SECOND_FIELD], -0.5);
+	}
+
+	#[test]
+	fn pipe_carries_at_most_its_capacity() {
+		let network = network(&[(10, FIELD, IRRIGATOR, 1.0)], pipe_stats(0.0, 1.0));
+		let specs = HashMap::from([(FIELD, field(5.0)), (IRRIGATOR, irrigator(2.0))]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, IRRIGATOR], |id| specs[&id]);
+
+		assert_near(flow.supply[&IRRIGATOR], 0.5);
+		assert_near(flow.pipe_loads[&10], 1.0);
+		assert_near(flow.rates[&FIELD], -1.0);
+	}
+
+	#[test]
+	fn network_without_sources_has_no_flow() {
+		let network = network(&[(10, IRRIGATOR, 5, 1.0)], pipe_stats(0.0, 10.0));
+
+		let flow = WaterFlow::compute(&network, &[IRRIGATOR, 5], |_| irrigator(1.0));
+
+		assert!(flow.powered.is_empty());
+		assert!(flow.pipe_ids.is_empty());
+		assert!(flow.pipe_loads.is_empty());
+	}
+
+	#[test]
+	fn long_pipes_lose_water() {
+		let network = network(&[(10, FIELD, IRRIGATOR, 10.0)], pipe_stats(0.05, 10.0));
+		let specs = HashMap::from([(FIELD, field(10.0)), (IRRIGATOR, irrigator(1.0))]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, IRRIGATOR], |id| specs[&id]);
+
+		// Half of the water is lost on the way
+		assert_near(flow.supply[&IRRIGATOR], 1.0);
+		assert_near(flow.pipe_loads[&10], 2.0);
+		assert_near(flow.rates[&FIELD], -2.0);
+	}
+
+	#[test]
+	fn tank_fills_through_capacity_left_by_consumers() {
+		let mut network = network(&[(10, FIELD, IRRIGATOR, 1.0)], pipe_stats(0.0, 2.0));
+		let segment = [Vector2::ZERO, Vector2::new(1.0, 0.0)];
+		network.insert(Pipe::new(
+			11,
+			IRRIGATOR,
+			TANK,
+			segment,
+			pipe_stats(0.0, 10.0),
+		));
+		let specs = HashMap::from([
+			(FIELD, field(5.0)),
+			(IRRIGATOR, irrigator(1.5)),
+			(TANK, tank(2.0, false, false)),
+		]);
+
+		let flow = WaterFlow::compute(&network, &[FIELD, IRRIGATOR, TANK], |id| specs[&id]);
+
+		assert_near(flow.supply[&IRRIGATOR], 1.0);
+		assert_near(flow.rates[&TANK], 0.5);
+		assert_near(flow.rates[&FIELD], -2.0);
+		assert_near(flow.pipe_loads[&10], 2.0);
+		assert_near(flow.pipe_loads[&11], 0.5);
+	}
+}